@@ -0,0 +1,114 @@
+//! The original version of the cave automaton.
+//!
+//! This is slow, and it has a few odd edge cases in how it counts walls, but
+//! every cave ever made came out of it. It's kept around, out of the library,
+//! so that the benchmarks and the mapgen tests can check `make_cellular_caves`
+//! against it. Both of them pull this file in as a module.
+
+use super::*;
+
+use std::collections::HashSet;
+
+pub fn cellular_caves_reference(width: usize, height: usize, gen: &mut PCG32) -> Result<VecImage<bool>, GenerationError> {
+  // utilities
+  let range_count = |buf: &VecImage<bool>, x: usize, y: usize, range: u32| {
+    debug_assert!(range > 0);
+    let mut total = 0;
+    for y in ((y as isize - range as isize) as usize)..=(y + range as usize) {
+      for x in ((x as isize - range as isize) as usize)..=(x + range as usize) {
+        if y == 0 && x == 0 {
+          continue;
+        } else {
+          match buf.get((x, y)) {
+            Some(&b) => if b {
+              total += 1;
+            },
+            None => {
+              total += 1;
+            }
+          }
+        }
+      }
+    }
+    total
+  };
+  let cave_copy = |src: &VecImage<bool>, dest: &mut VecImage<bool>| {
+    for (x, y, mut_ref) in dest.iter_mut() {
+      // TODO: this will count up some of the cells more than once, perhaps we
+      // can make this more efficient by making it more fiddly.
+      *mut_ref = range_count(src, x, y, 1) >= 5 || range_count(src, x, y, 2) <= 1;
+    }
+  };
+  let flood_copy = |src: &VecImage<bool>, dest: &mut VecImage<bool>, gen: &mut PCG32| {
+    dest.set_all(true);
+    let mut copied_count = 0;
+    let start = {
+      let d_width = RandRangeInclusive32::new(0..=((width - 1) as u32));
+      let d_height = RandRangeInclusive32::new(0..=((height - 1) as u32));
+      let mut x = d_width.roll_with(gen) as usize;
+      let mut y = d_height.roll_with(gen) as usize;
+      let mut tries = 0;
+      while src[(x, y)] {
+        x = d_width.roll_with(gen) as usize;
+        y = d_height.roll_with(gen) as usize;
+        tries += 1;
+        if tries > 100 {
+          return 0;
+        }
+      }
+      (x, y)
+    };
+    let mut open_set = HashSet::new();
+    let mut closed_set = HashSet::new();
+    open_set.insert(start);
+    while !open_set.is_empty() {
+      let loc: (usize, usize) = *open_set.iter().next().unwrap();
+      open_set.remove(&loc);
+      if closed_set.contains(&loc) {
+        continue;
+      } else {
+        closed_set.insert(loc);
+      };
+      if !src[loc] {
+        dest[loc] = false;
+        copied_count += 1;
+        if loc.0 > 1 {
+          open_set.insert((loc.0 - 1, loc.1));
+        }
+        if loc.0 < (src.width() - 2) {
+          open_set.insert((loc.0 + 1, loc.1));
+        }
+        if loc.1 > 1 {
+          open_set.insert((loc.0, loc.1 - 1));
+        }
+        if loc.1 < (src.height() - 2) {
+          open_set.insert((loc.0, loc.1 + 1));
+        }
+      }
+    }
+    copied_count
+  };
+
+  let d100 = RandRangeInclusive32::new(1..=100);
+  let mut buffer_a: VecImage<bool> = VecImage::new(width, height);
+  let mut buffer_b: VecImage<bool> = VecImage::new(width, height);
+
+  for _ in 0..CAVE_ATTEMPTS {
+    // fill the initial buffer, all cells 45% likely.
+    for (_x, _y, mut_ref) in buffer_a.iter_mut() {
+      *mut_ref = d100.roll_with(gen) <= 45;
+    }
+    // cave copy from A into B, then the reverse, 5 times total
+    cave_copy(&buffer_a, &mut buffer_b);
+    cave_copy(&buffer_b, &mut buffer_a);
+    cave_copy(&buffer_a, &mut buffer_b);
+    cave_copy(&buffer_b, &mut buffer_a);
+    cave_copy(&buffer_a, &mut buffer_b);
+    // good stuff is in B, flood copy back into A
+    let copied_count = flood_copy(&buffer_b, &mut buffer_a, gen);
+    if copied_count >= (width * height) / 2 {
+      return Ok(buffer_a);
+    }
+  }
+  Err(GenerationError::RetriesExhausted { attempts: CAVE_ATTEMPTS })
+}
@@ -22,17 +22,25 @@ const TILE_GRID_WIDTH: usize = 66;
 const TILE_GRID_HEIGHT: usize = 50;
 const KINDA_LIME_GREEN: u32 = rgb32!(128, 255, 20);
 
-pub const WALL_TILE: u8 = 11 + 13 * 16;
 pub const POTION_GLYPH: u8 = 13 + 10 * 16;
 pub const BOMB_GLYPH: u8 = 15 + 0 * 16;
 pub const ARMOR_GLYPH: u8 = 11 + 5 * 16;
 pub const WEAPON_GLYPH: u8 = 9 + 2 * 16;
+pub const FOOD_GLYPH: u8 = b'%';
+pub const KEY_GLYPH: u8 = b'-';
+pub const TRAP_GLYPH: u8 = b'^';
+pub const ARTIFACT_GLYPH: u8 = 4;
+
+/// The high scores live right next to the save file.
+const HIGH_SCORE_FILE: &str = "kasidin.scores";
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum DisplayMode {
   Game,
   Inventory,
   ItemTargeting(char, Location),
+  CloseDoor,
+  HighScores,
 }
 
 fn main() {
@@ -41,47 +49,84 @@ fn main() {
   term.set_all_backgrounds(0);
 
   let mut game = GameWorld::new(u64_from_time());
+  // keep paged out levels right next to the save file.
+  game.level_page_dir = Some(std::path::PathBuf::from("."));
 
   // Main loop
   let mut running = true;
   let mut pending_keys = vec![];
   let mut display_mode = DisplayMode::Game;
   let mut seen_set = HashSet::new();
+  let high_score_path = std::path::Path::new(HIGH_SCORE_FILE);
+  let mut high_scores = HighScores::load(high_score_path).unwrap_or_else(|e| {
+    eprintln!("Couldn't load the high scores: {}", e);
+    HighScores::default()
+  });
+  // where the run that just ended placed, and if it's been recorded yet.
+  let mut latest_rank = None;
+  let mut run_recorded = false;
   'game: loop {
     // Grab all new presses
-    term.poll_events(|event| match event {
-      Event::WindowEvent { event: win_event, .. } => match win_event {
-        WindowEvent::CloseRequested => {
-          running = false;
-        }
-        WindowEvent::KeyboardInput {
-          input:
-            KeyboardInput {
-              state: ElementState::Pressed,
-              virtual_keycode: Some(key),
-              modifiers: mods,
-              ..
-            },
-          ..
-        } => {
-          pending_keys.push((key, mods.shift));
+    term.poll_events(|event| {
+      if let Event::WindowEvent { event: win_event, .. } = event {
+        match win_event {
+          WindowEvent::CloseRequested => {
+            running = false;
+          }
+          WindowEvent::KeyboardInput {
+            input:
+              KeyboardInput {
+                state: ElementState::Pressed,
+                virtual_keycode: Some(key),
+                modifiers: mods,
+                ..
+              },
+            ..
+          } => {
+            pending_keys.push((key, mods.shift));
+          }
+          _ => {}
         }
-        _ => {}
-      },
-      _ => {}
+      }
     });
     if !running {
+      // anything worth keeping is in the save file already.
+      game.discard_level_pages();
       break 'game;
     }
 
     for key in pending_keys.drain(..) {
       match display_mode {
+        // once the game is over, all that's left is to load an old save
+        DisplayMode::Game if game.outcome.is_some() => match key {
+          (VirtualKeyCode::H, false) => display_mode = DisplayMode::HighScores,
+          (VirtualKeyCode::F6, false) => {
+            load_game(&mut game).ok();
+            // a finished game in the save was recorded when it finished.
+            run_recorded = game.outcome.is_some();
+          }
+          _ => {}
+        },
         DisplayMode::Game => match key {
           (VirtualKeyCode::Up, false) => game.move_player(Location { x: 0, y: 1, z: 0 }),
           (VirtualKeyCode::Down, false) => game.move_player(Location { x: 0, y: -1, z: 0 }),
           (VirtualKeyCode::Left, false) => game.move_player(Location { x: -1, y: 0, z: 0 }),
           (VirtualKeyCode::Right, false) => game.move_player(Location { x: 1, y: 0, z: 0 }),
           (VirtualKeyCode::I, false) => display_mode = DisplayMode::Inventory,
+          (VirtualKeyCode::C, false) => display_mode = DisplayMode::CloseDoor,
+          (VirtualKeyCode::S, false) => game.search(),
+          (VirtualKeyCode::H, false) => display_mode = DisplayMode::HighScores,
+          (VirtualKeyCode::F2, false) => {
+            // debug: what got spawned on this level. Stats aren't saved, so
+            // levels from a loaded game don't have any.
+            let z = game.player_location.z;
+            let report = match game.spawn_stats.get(&z) {
+              Some(stats) => format!("Level {}: {}", z, stats),
+              None => format!("No spawn stats for level {}", z),
+            };
+            println!("{}", report);
+            game.push_message(report);
+          }
           (VirtualKeyCode::F5, false) => {
             save_game(&game).ok();
           }
@@ -92,6 +137,11 @@ fn main() {
           (VirtualKeyCode::Comma, true) => game.change_floor(1),
           _ => {}
         },
+        DisplayMode::HighScores => {
+          if let (VirtualKeyCode::Escape, false) = key {
+            display_mode = DisplayMode::Game;
+          }
+        }
         DisplayMode::Inventory => match key {
           (VirtualKeyCode::Escape, false) => display_mode = DisplayMode::Game,
           (other, shift) => {
@@ -110,6 +160,17 @@ fn main() {
             });
           }
         },
+        DisplayMode::CloseDoor => {
+          // any key other than a direction just cancels
+          match key {
+            (VirtualKeyCode::Up, false) => game.close_door(Location { x: 0, y: 1, z: 0 }),
+            (VirtualKeyCode::Down, false) => game.close_door(Location { x: 0, y: -1, z: 0 }),
+            (VirtualKeyCode::Left, false) => game.close_door(Location { x: -1, y: 0, z: 0 }),
+            (VirtualKeyCode::Right, false) => game.close_door(Location { x: 1, y: 0, z: 0 }),
+            _ => {}
+          }
+          display_mode = DisplayMode::Game;
+        }
         DisplayMode::ItemTargeting(letter, delta) => match key {
           (VirtualKeyCode::Escape, false) => display_mode = DisplayMode::Game,
           (VirtualKeyCode::Return, false) => {
@@ -133,6 +194,16 @@ fn main() {
         },
       }
     }
+    // once the run is over it goes into the high scores, just the one time.
+    if !run_recorded {
+      if let Some(run) = game.run_record(unix_time_now()) {
+        latest_rank = high_scores.record(run);
+        high_scores.save(high_score_path).unwrap_or_else(|e| eprintln!("Couldn't save the high scores: {}", e));
+        run_recorded = true;
+        // nobody is going back down there now.
+        game.discard_level_pages();
+      }
+    }
     // assumes that the display is wider than tall
     const FOV_DISPLAY_RANGE: i32 = TILE_GRID_WIDTH as i32 / 2;
     // TODO: we should actually only adjust the seen set if the player moved. We
@@ -140,22 +211,22 @@ fn main() {
     // when necessary and then we just read that.
     seen_set.clear();
     let z = game.player_location.z;
+    let fov_range = {
+      let level_ref = &game.levels[&z];
+      FOV_DISPLAY_RANGE.min(level_ref.width.max(level_ref.height) as i32)
+    };
     ppfov(
       (game.player_location.x, game.player_location.y),
-      FOV_DISPLAY_RANGE,
-      |x, y| {
-        game
-          .terrain
-          .get(&Location { x, y, z })
-          .map(|&t| t == Terrain::Wall || t == Terrain::Ice)
-          .unwrap_or(true)
-      },
+      fov_range,
+      |x, y| !game.terrain_at(Location { x, y, z }).is_transparent(),
       |x, y| drop(seen_set.insert(Location { x, y, z })),
     );
     {
       match display_mode {
-        DisplayMode::Game => draw_game(&mut term, &game, &seen_set),
+        DisplayMode::Game if game.outcome == Some(GameOutcome::Won) => draw_victory(&mut term, &game),
+        DisplayMode::Game | DisplayMode::CloseDoor => draw_game(&mut term, &game, &seen_set),
         DisplayMode::Inventory => draw_inventory(&mut term, &game),
+        DisplayMode::HighScores => draw_high_scores(&mut term, &high_scores, latest_rank),
         DisplayMode::ItemTargeting(_letter, delta) => draw_targeting(&mut term, &game, &seen_set, delta),
       }
     }
@@ -212,15 +283,12 @@ fn draw_game(term: &mut DwarfTerm, game: &GameWorld, seen_set: &HashSet<Location
   bgs.set_all(rgb32!(0, 0, 0));
   ids.set_all(0);
 
-  let offset = game.player_location - Location {
-    x: (fgs.width() / 2) as i32,
-    y: (fgs.height() / 2) as i32,
-    z: game.player_location.z,
-  };
-  // draw the map, save space for the status line.
+  // draw the map, save space for the message line and status line.
   const STATUS_HEIGHT: usize = 1;
+  const MESSAGE_HEIGHT: usize = 1;
   let full_extent = (ids.width(), ids.height());
-  let map_view_end = (full_extent.0, full_extent.1 - STATUS_HEIGHT);
+  let map_view_end = (full_extent.0, full_extent.1 - STATUS_HEIGHT - MESSAGE_HEIGHT);
+  let offset = camera_offset(game, map_view_end);
   for (scr_x, scr_y, id_mut) in ids.slice_mut((0, 0)..map_view_end).iter_mut() {
     let loc_for_this_screen_position = Location {
       x: scr_x as i32,
@@ -245,11 +313,19 @@ fn draw_game(term: &mut DwarfTerm, game: &GameWorld, seen_set: &HashSet<Location
             .get(&loc_for_this_screen_position)
             .and_then(|item_vec_ref| item_vec_ref.get(0))
             .map(|item_ref| display_of_item(*item_ref))
-            .unwrap_or_else(|| {
+            .or_else(|| {
               game
-                .terrain
+                .trap_locations
                 .get(&loc_for_this_screen_position)
-                .map(|terrain_ref| display_of_terrain(*terrain_ref))
+                .filter(|trap_ref| !trap_ref.hidden)
+                .map(|trap_ref| display_of_trap(trap_ref.kind))
+            })
+            .unwrap_or_else(|| {
+              game
+                .levels
+                .get(&loc_for_this_screen_position.z)
+                .and_then(|level_ref| level_ref.get(loc_for_this_screen_position.x, loc_for_this_screen_position.y))
+                .map(|terrain| display_of_terrain(terrain, theme_for_depth(loc_for_this_screen_position.z)))
                 .unwrap_or((b' ', 0))
             })
         })
@@ -259,20 +335,41 @@ fn draw_game(term: &mut DwarfTerm, game: &GameWorld, seen_set: &HashSet<Location
     *id_mut = glyph;
     fgs[(scr_x, scr_y)] = color;
   }
-  // draw the status bar.
+  // draw the most recent message.
+  let status_start = (0, map_view_end.1 + MESSAGE_HEIGHT);
   fgs.slice_mut((0, map_view_end.1)..full_extent).set_all(KINDA_LIME_GREEN);
   bgs.slice_mut((0, map_view_end.1)..full_extent).set_all(rgb32!(0, 0, 0));
-  let mut ids_status_slice_mut = ids.slice_mut((0, map_view_end.1)..full_extent);
+  ids.slice_mut((0, map_view_end.1)..status_start).set_all(0);
+  debug_assert_eq!(1, MESSAGE_HEIGHT);
+  if let Some(message) = game.messages.last() {
+    let mut message_line_u8_slice_mut: &mut [u8] =
+      unsafe { ::std::slice::from_raw_parts_mut(ids.as_mut_ptr().offset(ids.pitch() * map_view_end.1 as isize), full_extent.0) };
+    write!(message_line_u8_slice_mut, "{}", message).ok();
+  }
+  let player_ref = game.creature_list.iter().find(|creature_ref| creature_ref.is_the_player).unwrap();
+  let hunger = player_ref.hunger_state();
+  if hunger != HungerState::Fed {
+    let hunger_label = format!("{}", hunger);
+    let x_offset = (full_extent.0 - hunger_label.len()) as isize;
+    let mut hunger_u8_slice_mut: &mut [u8] =
+      unsafe { ::std::slice::from_raw_parts_mut(ids.as_mut_ptr().offset(x_offset + ids.pitch() * map_view_end.1 as isize), hunger_label.len()) };
+    write!(hunger_u8_slice_mut, "{}", hunger_label).ok();
+  }
+  // draw the status bar.
+  let mut ids_status_slice_mut = ids.slice_mut(status_start..full_extent);
   debug_assert_eq!(ids_status_slice_mut.width(), full_extent.0);
   debug_assert_eq!(ids_status_slice_mut.height(), STATUS_HEIGHT);
   ids_status_slice_mut.set_all(0);
   debug_assert_eq!(1, STATUS_HEIGHT);
   let mut status_line_u8_slice_mut: &mut [u8] = unsafe { ::std::slice::from_raw_parts_mut(ids_status_slice_mut.as_mut_ptr(), full_extent.0) };
-  let player_ref = game.creature_list.iter().find(|creature_ref| creature_ref.is_the_player).unwrap();
   write!(
     status_line_u8_slice_mut,
-    "HP: {}, Enemies: {}, Z:{}, Damage Step: {}, Armor: {}",
+    "HP: {}/{}, Lv {} ({}/{}xp), Enemies: {}, Z:{}, Dmg Step: {}, Armor: {}",
     player_ref.hit_points,
+    player_ref.max_hit_points,
+    player_ref.character_level,
+    player_ref.experience,
+    experience_to_reach(player_ref.character_level + 1),
     game.creature_list.len() - 1,
     game.player_location.z,
     player_ref.damage_step,
@@ -280,6 +377,26 @@ fn draw_game(term: &mut DwarfTerm, game: &GameWorld, seen_set: &HashSet<Location
   ).ok();
 }
 
+/// Picks the map location that the bottom left of the view shows.
+///
+/// Levels that fit on screen are centered, larger levels follow the player but
+/// stop scrolling at the edges of the level.
+fn camera_offset(game: &GameWorld, (view_width, view_height): (usize, usize)) -> Location {
+  let level_ref = &game.levels[&game.player_location.z];
+  let axis_offset = |level_size: usize, view_size: usize, player_pos: i32| {
+    if level_size <= view_size {
+      (level_size as i32 - view_size as i32) / 2
+    } else {
+      (player_pos - view_size as i32 / 2).max(0).min((level_size - view_size) as i32)
+    }
+  };
+  Location {
+    x: axis_offset(level_ref.width, view_width, game.player_location.x),
+    y: axis_offset(level_ref.height, view_height, game.player_location.y),
+    z: 0,
+  }
+}
+
 fn draw_inventory(term: &mut DwarfTerm, game: &GameWorld) {
   let (mut fgs, mut bgs, mut ids) = term.layer_slices_mut();
   // clear the display
@@ -342,6 +459,83 @@ fn draw_inventory(term: &mut DwarfTerm, game: &GameWorld) {
   }
 }
 
+fn draw_victory(term: &mut DwarfTerm, game: &GameWorld) {
+  let (mut fgs, mut bgs, mut ids) = term.layer_slices_mut();
+  // clear the display
+  fgs.set_all(rgb32!(255, 201, 14));
+  bgs.set_all(rgb32!(0, 0, 0));
+  ids.set_all(0);
+
+  let lines = [
+    "== Victory! ==".to_string(),
+    String::new(),
+    format!("You escaped with the {}.", ARTIFACT_NAME),
+    String::new(),
+    format!("Deepest level: {}", TOP_LEVEL_Z - game.deepest_reached),
+    format!("Turns taken: {}", game.turn_count),
+    String::new(),
+    "Press H for the high scores, or F6 to load a saved game.".to_string(),
+  ];
+  let mut the_y_position: isize = ids.height() as isize - 3;
+  for line in lines.iter() {
+    assert!(line.len() < ids.width());
+    let x_offset = (ids.width() - line.len()) as isize / 2;
+    let mut this_line_slice_mut: &mut [u8] =
+      unsafe { ::std::slice::from_raw_parts_mut(ids.as_mut_ptr().offset(x_offset + the_y_position * ids.pitch()), line.len()) };
+    write!(this_line_slice_mut, "{}", line).ok();
+    the_y_position -= 1;
+  }
+}
+
+fn draw_high_scores(term: &mut DwarfTerm, high_scores: &HighScores, latest_rank: Option<usize>) {
+  let (mut fgs, mut bgs, mut ids) = term.layer_slices_mut();
+  // clear the display
+  fgs.set_all(rgb32!(255, 255, 255));
+  bgs.set_all(rgb32!(0, 0, 0));
+  ids.set_all(0);
+
+  // draw the menu title
+  {
+    let menu_title = "== High Scores ==";
+    assert!(menu_title.len() < ids.width());
+    let x_offset = (ids.width() - menu_title.len()) as isize / 2;
+    let y_offset = (ids.height() as isize - 1) as isize;
+    let mut this_line_slice_mut: &mut [u8] =
+      unsafe { ::std::slice::from_raw_parts_mut(ids.as_mut_ptr().offset(x_offset + y_offset * ids.pitch()), menu_title.len()) };
+    write!(this_line_slice_mut, "{}", menu_title).ok();
+  }
+  // draw the runs, two lines each, with the one that just ended picked out
+  if !high_scores.runs.is_empty() {
+    let mut the_y_position: isize = ids.height() as isize - 3;
+    for (i, run) in high_scores.runs.iter().enumerate() {
+      if the_y_position < 1 {
+        break;
+      }
+      let lines = [
+        format!("{:>2}) {:>6}  {}  depth {}, {} kills, {} turns", i + 1, run.score, run.date(), run.depth, run.kills, run.turns),
+        format!("    seed {}, {}", run.seed, run.outcome),
+      ];
+      for line in lines.iter() {
+        let mut this_line_slice_mut: &mut [u8] =
+          unsafe { ::std::slice::from_raw_parts_mut(ids.as_mut_ptr().offset(ids.pitch() * the_y_position), ids.width()) };
+        write!(this_line_slice_mut, "{}", line).ok();
+        if latest_rank == Some(i) {
+          fgs.slice_mut((0, the_y_position as usize)..(ids.width(), the_y_position as usize + 1)).set_all(rgb32!(255, 201, 14));
+        }
+        the_y_position -= 1;
+      }
+    }
+  } else {
+    let message = "Nobody has finished a run yet.";
+    assert!(message.len() < ids.width());
+    let x_offset = (ids.width() - message.len()) as isize / 2;
+    let y_offset = (ids.height() as isize - 3) as isize;
+    let mut this_line_slice_mut: &mut [u8] =
+      unsafe { ::std::slice::from_raw_parts_mut(ids.as_mut_ptr().offset(x_offset + y_offset * ids.pitch()), message.len()) };
+    write!(this_line_slice_mut, "{}", message).ok();
+  }
+}
+
 fn draw_targeting(term: &mut DwarfTerm, game: &GameWorld, seen_set: &HashSet<Location>, delta: Location) {
   let (mut fgs, mut bgs, mut ids) = term.layer_slices_mut();
   // clear the display
@@ -360,16 +554,12 @@ fn draw_targeting(term: &mut DwarfTerm, game: &GameWorld, seen_set: &HashSet<Loc
     write!(this_line_slice_mut, "{}", menu_title).ok();
   }
 
-  let offset = game.player_location - Location {
-    x: (fgs.width() / 2) as i32,
-    y: (fgs.height() / 2) as i32,
-    z: game.player_location.z,
-  };
   let target_delta_location = game.player_location + delta;
   // draw the map, save space for the status line.
   const STATUS_HEIGHT: usize = 1;
   let full_extent = (ids.width(), ids.height());
   let map_view_end = (full_extent.0, full_extent.1 - STATUS_HEIGHT);
+  let offset = camera_offset(game, map_view_end);
   for (scr_x, scr_y, id_mut) in ids.slice_mut((0, 0)..map_view_end).iter_mut() {
     let loc_for_this_screen_position = Location {
       x: scr_x as i32,
@@ -394,11 +584,19 @@ fn draw_targeting(term: &mut DwarfTerm, game: &GameWorld, seen_set: &HashSet<Loc
             .get(&loc_for_this_screen_position)
             .and_then(|item_vec_ref| item_vec_ref.get(0))
             .map(|item_ref| display_of_item(*item_ref))
-            .unwrap_or_else(|| {
+            .or_else(|| {
               game
-                .terrain
+                .trap_locations
                 .get(&loc_for_this_screen_position)
-                .map(|terrain_ref| display_of_terrain(*terrain_ref))
+                .filter(|trap_ref| !trap_ref.hidden)
+                .map(|trap_ref| display_of_trap(trap_ref.kind))
+            })
+            .unwrap_or_else(|| {
+              game
+                .levels
+                .get(&loc_for_this_screen_position.z)
+                .and_then(|level_ref| level_ref.get(loc_for_this_screen_position.x, loc_for_this_screen_position.y))
+                .map(|terrain| display_of_terrain(terrain, theme_for_depth(loc_for_this_screen_position.z)))
                 .unwrap_or((b' ', 0))
             })
         })
@@ -416,9 +614,9 @@ fn draw_targeting(term: &mut DwarfTerm, game: &GameWorld, seen_set: &HashSet<Loc
 }
 
 fn save_game(game: &GameWorld) -> std::io::Result<()> {
+  // paged out levels go into the save too, so the page files don't matter.
+  let encoded: Vec<u8> = game.to_save_bytes()?;
   let mut f = std::fs::File::create("kasidin.save")?;
-  let encoded: Vec<u8> =
-    bincode::serialize(&game).map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Couldn't serialize the game!"))?;
   f.write_all(&encoded)
 }
 
@@ -426,9 +624,9 @@ fn load_game(game: &mut GameWorld) -> std::io::Result<()> {
   let mut f = std::fs::File::open("kasidin.save")?;
   let mut file_bytes: Vec<u8> = vec![];
   f.read_to_end(&mut file_bytes)?;
-  let decoded: GameWorld =
-    bincode::deserialize(&file_bytes).map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Couldn't deserialize the game!"))?;
-  *game = decoded;
+  let loaded = GameWorld::from_save_bytes(&file_bytes)?;
+  game.discard_level_pages();
+  *game = loaded;
   Ok(())
 }
 
@@ -438,20 +636,27 @@ fn display_of_item(item: Item) -> (u8, u32) {
     Item::PotionStrength => (POTION_GLYPH, rgb32!(5, 240, 20)),
     Item::BombBlast => (BOMB_GLYPH, rgb32!(127, 127, 127)),
     Item::BombIce => (BOMB_GLYPH, rgb32!(153, 217, 234)),
+    Item::FoodRock => (FOOD_GLYPH, rgb32!(185, 122, 87)),
+    Item::FoodSoup => (FOOD_GLYPH, rgb32!(255, 100, 0)),
+    Item::Key => (KEY_GLYPH, rgb32!(255, 201, 14)),
     Item::CrystalPlate(_) => (ARMOR_GLYPH, rgb32!(0, 162, 232)),
     Item::LobsterMail(_) => (ARMOR_GLYPH, rgb32!(237, 28, 36)),
     Item::Fernweave(_) => (ARMOR_GLYPH, rgb32!(34, 177, 76)),
     Item::Dagger(_) => (WEAPON_GLYPH, rgb32!(195, 195, 195)),
     Item::Warhammer(_) => (WEAPON_GLYPH, rgb32!(127, 127, 127)),
+    Item::Artifact => (ARTIFACT_GLYPH, rgb32!(255, 201, 14)),
   }
 }
 
-fn display_of_terrain(terrain: Terrain) -> (u8, u32) {
-  match terrain {
-    Terrain::Wall => (WALL_TILE, rgb32!(155, 75, 0)),
-    Terrain::Ice => (WALL_TILE, rgb32!(112, 146, 190)),
-    Terrain::Floor => (b'.', rgb32!(128, 128, 128)),
-    Terrain::StairsDown => (b'>', rgb32!(190, 190, 190)),
-    Terrain::StairsUp => (b'<', rgb32!(190, 190, 190)),
+fn display_of_trap(kind: TrapKind) -> (u8, u32) {
+  match kind {
+    TrapKind::Teleport => (TRAP_GLYPH, rgb32!(163, 73, 164)),
+    TrapKind::Pit => (TRAP_GLYPH, rgb32!(185, 122, 87)),
+    TrapKind::Alarm => (TRAP_GLYPH, rgb32!(255, 201, 14)),
+    TrapKind::Dart => (TRAP_GLYPH, rgb32!(195, 195, 195)),
   }
 }
+
+fn display_of_terrain(terrain: Terrain, theme: Theme) -> (u8, u32) {
+  (terrain.properties().glyph, theme.terrain_color(terrain))
+}
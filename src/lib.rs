@@ -9,21 +9,56 @@ extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 
+extern crate bincode;
+
 // std
 pub(crate) use std::collections::hash_map::*;
 pub(crate) use std::collections::hash_set::*;
-pub(crate) use std::collections::BTreeMap;
+pub(crate) use std::collections::{BTreeMap, BTreeSet};
 pub(crate) use std::ops::*;
 
+pub mod line;
+pub use line::*;
+pub mod mapgen;
+pub use mapgen::*;
+pub mod paging;
+pub use paging::*;
 pub mod pathing;
 pub use pathing::*;
+pub mod placement;
+pub use placement::*;
 pub mod precise_permissive_fov;
 pub use precise_permissive_fov::*;
+pub mod pregen;
+pub use pregen::*;
 pub mod prng;
 pub use prng::*;
+pub mod quest;
+pub use quest::*;
+pub mod regions;
+pub use regions::*;
+pub mod scores;
+pub use scores::*;
+pub mod simulation;
+pub use simulation::*;
+pub mod spawning;
+pub use spawning::*;
+pub mod stairs;
+pub use stairs::*;
+pub mod themes;
+pub use themes::*;
+pub mod traps;
+pub use traps::*;
+pub mod vaults;
+pub use vaults::*;
 
 pub const TERULO_BROWN: u32 = rgb32!(197, 139, 5);
 pub const KESTREL_RED: u32 = rgb32!(166, 0, 0);
+pub const SPITTER_ORANGE: u32 = rgb32!(255, 127, 39);
+pub const THROWER_GRAY: u32 = rgb32!(160, 160, 160);
+pub const SHAMAN_PURPLE: u32 = rgb32!(163, 73, 164);
+
+pub const WALL_TILE: u8 = 11 + 13 * 16;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum Item {
@@ -36,6 +71,12 @@ pub enum Item {
   Fernweave(i8),
   LobsterMail(i8),
   CrystalPlate(i8),
+  // New items go at the end, so that older saves keep loading.
+  FoodRock,
+  FoodSoup,
+  Key,
+  /// The one thing worth coming down here for.
+  Artifact,
 }
 
 impl Item {
@@ -55,11 +96,19 @@ impl Item {
       _ => 0,
     }
   }
+
+  fn nutrition_value(self) -> i32 {
+    match self {
+      Item::FoodRock => 300,
+      Item::FoodSoup => 700,
+      _ => 0,
+    }
+  }
 }
 
 fn apply_potion(potion: &Item, target: &mut Creature, rng: &mut PCG32) {
   match potion {
-    Item::PotionHealth => target.hit_points = (target.hit_points + step(rng, 8)).min(30),
+    Item::PotionHealth => target.hit_points = (target.hit_points + step(rng, 8)).min(target.max_hit_points),
     Item::PotionStrength => target.damage_step += 1,
     _ => panic!("not a potion {}", potion),
   }
@@ -72,11 +121,15 @@ impl ::std::fmt::Display for Item {
       Item::PotionStrength => write!(f, "Potion of Gain Strength"),
       Item::BombBlast => write!(f, "Blast Bomb"),
       Item::BombIce => write!(f, "Ice Bomb"),
+      Item::FoodRock => write!(f, "Crunchy Rock"),
+      Item::FoodSoup => write!(f, "Flask of Spicy Lava Soup"),
+      Item::Key => write!(f, "Iron Key"),
       Item::Dagger(x) => write!(f, "Dagger {}{}", if *x >= 0 { "+" } else { "-" }, x),
       Item::Warhammer(x) => write!(f, "Warhammer {}{}", if *x >= 0 { "+" } else { "-" }, x),
       Item::Fernweave(x) => write!(f, "Fernweave {}{}", if *x >= 0 { "+" } else { "-" }, x),
       Item::LobsterMail(x) => write!(f, "Lobster Mail {}{}", if *x >= 0 { "+" } else { "-" }, x),
       Item::CrystalPlate(x) => write!(f, "Crystal Plate {}{}", if *x >= 0 { "+" } else { "-" }, x),
+      Item::Artifact => write!(f, "{}", ARTIFACT_NAME),
     }
   }
 }
@@ -145,6 +198,11 @@ impl Location {
       index: 0,
     }
   }
+
+  /// Steps needed to walk to `other` on the same level, ignoring obstacles.
+  pub fn manhattan_distance(&self, other: Location) -> i32 {
+    (self.x - other.x).abs() + (self.y - other.y).abs()
+  }
 }
 
 impl Add for Location {
@@ -176,11 +234,22 @@ pub struct Creature {
   pub is_the_player: bool,
   pub id: CreatureID,
   pub hit_points: i32,
+  pub max_hit_points: i32,
   pub damage_step: i32,
   pub armor: i32,
   pub inventory: Vec<Item>,
   pub equipped_weapon: Option<Item>,
   pub equipped_armor: Option<Item>,
+  pub kind: Option<MonsterKind>,
+  pub ammo: u32,
+  pub character_level: u32,
+  pub experience: u32,
+  /// Counts down each turn, the player starves when it hits zero.
+  pub nutrition: i32,
+  /// How much experience the player gets for killing this creature.
+  pub experience_value: u32,
+  /// Where a monster heard a noise, it goes to look when it can't see anyone.
+  pub alerted_to: Option<Location>,
 }
 impl Creature {
   fn new(cid: usize, icon: u8, color: u32) -> Self {
@@ -190,11 +259,19 @@ impl Creature {
       is_the_player: false,
       id: CreatureID(cid),
       hit_points: 1,
+      max_hit_points: 1,
       damage_step: 1,
       armor: 0,
       inventory: vec![],
       equipped_weapon: None,
       equipped_armor: None,
+      kind: None,
+      ammo: 0,
+      character_level: 1,
+      experience: 0,
+      experience_value: 0,
+      nutrition: STARTING_NUTRITION,
+      alerted_to: None,
     }
   }
 
@@ -202,16 +279,206 @@ impl Creature {
     let mut out = Self::new(cid, b'@', TERULO_BROWN);
     out.is_the_player = true;
     out.hit_points = 20;
+    out.max_hit_points = 20;
     out.damage_step = 5;
     out
   }
 
-  fn new_kestrel(cid: usize, depth: i32) -> Self {
-    let mut out = Self::new(cid, b'k', KESTREL_RED);
-    out.hit_points = 8 + depth.abs();
-    out.damage_step = 3;
+  fn new_monster(cid: usize, kind: MonsterKind, depth: i32) -> Self {
+    let definition = kind.definition();
+    let mut out = Self::new(cid, definition.icon, definition.color);
+    out.hit_points = definition.base_hit_points + depth.abs();
+    out.max_hit_points = out.hit_points;
+    out.damage_step = definition.damage_step;
+    out.kind = Some(kind);
+    out.ammo = definition.ranged.map(|ranged| ranged.ammo).unwrap_or(0);
+    out.experience_value = (out.hit_points + 2 * depth.abs()) as u32;
     out
   }
+
+  pub fn hunger_state(&self) -> HungerState {
+    if self.nutrition <= 50 {
+      HungerState::Fainting
+    } else if self.nutrition <= 150 {
+      HungerState::Weak
+    } else if self.nutrition <= 400 {
+      HungerState::Hungry
+    } else {
+      HungerState::Fed
+    }
+  }
+
+  /// The ranged attack this creature can still make, if any.
+  fn ready_ranged_attack(&self) -> Option<RangedAttack> {
+    self
+      .kind
+      .and_then(|kind| kind.definition().ranged)
+      .filter(|_| self.ammo > 0)
+  }
+}
+
+pub const STARTING_NUTRITION: i32 = 1000;
+pub const MAX_NUTRITION: i32 = 1500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HungerState {
+  Fed,
+  Hungry,
+  Weak,
+  Fainting,
+}
+
+impl HungerState {
+  /// How much the player's damage step is reduced by.
+  pub fn damage_penalty(self) -> i32 {
+    match self {
+      HungerState::Fed | HungerState::Hungry => 0,
+      HungerState::Weak => 1,
+      HungerState::Fainting => 2,
+    }
+  }
+}
+
+impl ::std::fmt::Display for HungerState {
+  fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+    match self {
+      HungerState::Fed => write!(f, "Fed"),
+      HungerState::Hungry => write!(f, "Hungry"),
+      HungerState::Weak => write!(f, "Weak"),
+      HungerState::Fainting => write!(f, "Fainting"),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeathCause {
+  Slain(MonsterKind),
+  Starvation,
+  Trap(TrapKind),
+  Fell,
+}
+
+/// How a game ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GameOutcome {
+  Died(DeathCause),
+  /// Made it back out of the dungeon with the artifact.
+  Won,
+}
+
+impl ::std::fmt::Display for GameOutcome {
+  fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+    match self {
+      GameOutcome::Died(cause) => write!(f, "{}", cause),
+      GameOutcome::Won => write!(f, "escaped with the {}", ARTIFACT_NAME),
+    }
+  }
+}
+
+impl ::std::fmt::Display for DeathCause {
+  fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+    match self {
+      DeathCause::Slain(kind) => write!(f, "slain by a {}", kind.definition().name),
+      DeathCause::Starvation => write!(f, "starved to death"),
+      DeathCause::Trap(kind) => write!(f, "killed by a {}", kind),
+      DeathCause::Fell => write!(f, "killed by a long fall"),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum MonsterKind {
+  Kestrel,
+  MagmaSpitter,
+  RockThrower,
+  AshShaman,
+}
+
+/// A ranged attack that a monster can make at a distance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RangedAttack {
+  pub verb: &'static str,
+  pub range: i32,
+  pub damage_step: i32,
+  /// How many shots the monster starts with.
+  pub ammo: u32,
+}
+
+/// The total experience needed to reach the given character level.
+pub fn experience_to_reach(character_level: u32) -> u32 {
+  10 * character_level.saturating_sub(1) * character_level
+}
+
+/// The stat block that every monster of a given kind starts with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MonsterDefinition {
+  pub name: &'static str,
+  pub icon: u8,
+  pub color: u32,
+  /// Hit points before the depth bonus is added.
+  pub base_hit_points: i32,
+  pub damage_step: i32,
+  pub ranged: Option<RangedAttack>,
+  /// Ranged monsters try to stay at least this far from the player.
+  pub preferred_distance: i32,
+}
+
+impl MonsterKind {
+  pub fn definition(self) -> MonsterDefinition {
+    match self {
+      MonsterKind::Kestrel => MonsterDefinition {
+        name: "Kestrel",
+        icon: b'k',
+        color: KESTREL_RED,
+        base_hit_points: 8,
+        damage_step: 3,
+        ranged: None,
+        preferred_distance: 0,
+      },
+      MonsterKind::MagmaSpitter => MonsterDefinition {
+        name: "Magma Spitter",
+        icon: b's',
+        color: SPITTER_ORANGE,
+        base_hit_points: 5,
+        damage_step: 2,
+        ranged: Some(RangedAttack {
+          verb: "spits magma at",
+          range: 4,
+          damage_step: 4,
+          ammo: 6,
+        }),
+        preferred_distance: 3,
+      },
+      MonsterKind::RockThrower => MonsterDefinition {
+        name: "Rock Thrower",
+        icon: b't',
+        color: THROWER_GRAY,
+        base_hit_points: 10,
+        damage_step: 3,
+        ranged: Some(RangedAttack {
+          verb: "throws a rock at",
+          range: 6,
+          damage_step: 5,
+          ammo: 3,
+        }),
+        preferred_distance: 2,
+      },
+      MonsterKind::AshShaman => MonsterDefinition {
+        name: "Ash Shaman",
+        icon: b'a',
+        color: SHAMAN_PURPLE,
+        base_hit_points: 6,
+        damage_step: 1,
+        ranged: Some(RangedAttack {
+          verb: "casts a cinder bolt at",
+          range: 7,
+          damage_step: 6,
+          ammo: 20,
+        }),
+        preferred_distance: 4,
+      },
+    }
+  }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -221,6 +488,11 @@ pub enum Terrain {
   Ice,
   StairsDown,
   StairsUp,
+  DoorClosed,
+  DoorOpen,
+  DoorLocked,
+  Pit,
+  Trapdoor,
 }
 
 impl Default for Terrain {
@@ -229,114 +501,277 @@ impl Default for Terrain {
   }
 }
 
-fn make_cellular_caves(width: usize, height: usize, gen: &mut PCG32) -> VecImage<bool> {
-  // utilities
-  let range_count = |buf: &VecImage<bool>, x: usize, y: usize, range: u32| {
-    debug_assert!(range > 0);
-    let mut total = 0;
-    for y in ((y as isize - range as isize) as usize)..=(y + range as usize) {
-      for x in ((x as isize - range as isize) as usize)..=(x + range as usize) {
-        if y == 0 && x == 0 {
-          continue;
-        } else {
-          match buf.get((x, y)) {
-            Some(&b) => if b {
-              total += 1;
-            },
-            None => {
-              total += 1;
-            }
-          }
-        }
-      }
-    }
-    total
-  };
-  let cave_copy = |src: &VecImage<bool>, dest: &mut VecImage<bool>| {
-    for (x, y, mut_ref) in dest.iter_mut() {
-      // TODO: this will count up some of the cells more than once, perhaps we
-      // can make this more efficient by making it more fiddly.
-      *mut_ref = range_count(src, x, y, 1) >= 5 || range_count(src, x, y, 2) <= 1;
-    }
-  };
-  let flood_copy = |src: &VecImage<bool>, dest: &mut VecImage<bool>, gen: &mut PCG32| {
-    dest.set_all(true);
-    let mut copied_count = 0;
-    let start = {
-      let d_width = RandRangeInclusive32::new(0..=((width - 1) as u32));
-      let d_height = RandRangeInclusive32::new(0..=((height - 1) as u32));
-      let mut x = d_width.roll_with(gen) as usize;
-      let mut y = d_height.roll_with(gen) as usize;
-      let mut tries = 0;
-      while src[(x, y)] {
-        x = d_width.roll_with(gen) as usize;
-        y = d_height.roll_with(gen) as usize;
-        tries += 1;
-        if tries > 100 {
-          return 0;
-        }
-      }
-      (x, y)
-    };
-    let mut open_set = HashSet::new();
-    let mut closed_set = HashSet::new();
-    open_set.insert(start);
-    while !open_set.is_empty() {
-      let loc: (usize, usize) = *open_set.iter().next().unwrap();
-      open_set.remove(&loc);
-      if closed_set.contains(&loc) {
-        continue;
-      } else {
-        closed_set.insert(loc);
-      };
-      if !src[loc] {
-        dest[loc] = false;
-        copied_count += 1;
-        if loc.0 > 1 {
-          open_set.insert((loc.0 - 1, loc.1));
-        }
-        if loc.0 < (src.width() - 2) {
-          open_set.insert((loc.0 + 1, loc.1));
-        }
-        if loc.1 > 1 {
-          open_set.insert((loc.0, loc.1 - 1));
-        }
-        if loc.1 < (src.height() - 2) {
-          open_set.insert((loc.0, loc.1 + 1));
-        }
-      }
-    }
-    copied_count
-  };
-
-  let d100 = RandRangeInclusive32::new(1..=100);
-  let mut buffer_a: VecImage<bool> = VecImage::new(width, height);
-  let mut buffer_b: VecImage<bool> = VecImage::new(width, height);
-
-  'work: loop {
-    // fill the initial buffer, all cells 45% likely.
-    for (_x, _y, mut_ref) in buffer_a.iter_mut() {
-      *mut_ref = d100.roll_with(gen) <= 45;
-    }
-    // cave copy from A into B, then the reverse, 5 times total
-    cave_copy(&buffer_a, &mut buffer_b);
-    cave_copy(&buffer_b, &mut buffer_a);
-    cave_copy(&buffer_a, &mut buffer_b);
-    cave_copy(&buffer_b, &mut buffer_a);
-    cave_copy(&buffer_a, &mut buffer_b);
-    // good stuff is in B, flood copy back into A
-    let copied_count = flood_copy(&buffer_b, &mut buffer_a, gen);
-    if copied_count >= (width * height) / 2 {
-      return buffer_a;
-    } else {
-      continue 'work;
+/// Everything that the game needs to know about a kind of terrain.
+///
+/// All of the game's systems should check these properties rather than
+/// matching on specific terrain, so that adding new terrain is safe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TerrainProperties {
+  pub name: &'static str,
+  /// Creatures can stand here.
+  pub walkable: bool,
+  /// Sight and projectiles can pass through.
+  pub transparent: bool,
+  /// Blast bombs burn this away into floor.
+  pub flammable: bool,
+  /// Blast bombs shatter this into floor.
+  pub shatters: bool,
+  /// Ice bombs freeze this into ice.
+  pub freezes: bool,
+  /// What this becomes when someone opens it.
+  pub opens_into: Option<Terrain>,
+  /// What this becomes when someone closes it.
+  pub closes_into: Option<Terrain>,
+  /// Opening this takes a key.
+  pub locked: bool,
+  /// Anything that steps here drops down to the next level.
+  pub falls_through: bool,
+  /// Looks just like plain floor, so nobody knows to avoid it.
+  pub hidden: bool,
+  pub glyph: u8,
+  pub color: u32,
+}
+
+impl Terrain {
+  pub fn properties(self) -> TerrainProperties {
+    match self {
+      Terrain::Wall => TerrainProperties {
+        name: "wall",
+        walkable: false,
+        transparent: false,
+        flammable: false,
+        shatters: false,
+        freezes: false,
+        opens_into: None,
+        closes_into: None,
+        locked: false,
+        falls_through: false,
+        hidden: false,
+        glyph: WALL_TILE,
+        color: rgb32!(155, 75, 0),
+      },
+      Terrain::Floor => TerrainProperties {
+        name: "floor",
+        walkable: true,
+        transparent: true,
+        flammable: false,
+        shatters: false,
+        freezes: true,
+        opens_into: None,
+        closes_into: None,
+        locked: false,
+        falls_through: false,
+        hidden: false,
+        glyph: b'.',
+        color: rgb32!(128, 128, 128),
+      },
+      Terrain::Ice => TerrainProperties {
+        name: "ice",
+        walkable: false,
+        transparent: false,
+        flammable: false,
+        shatters: true,
+        freezes: false,
+        opens_into: None,
+        closes_into: None,
+        locked: false,
+        falls_through: false,
+        hidden: false,
+        glyph: WALL_TILE,
+        color: rgb32!(112, 146, 190),
+      },
+      Terrain::StairsDown => TerrainProperties {
+        name: "stairs down",
+        walkable: true,
+        transparent: true,
+        flammable: false,
+        shatters: false,
+        freezes: false,
+        opens_into: None,
+        closes_into: None,
+        locked: false,
+        falls_through: false,
+        hidden: false,
+        glyph: b'>',
+        color: rgb32!(190, 190, 190),
+      },
+      Terrain::StairsUp => TerrainProperties {
+        name: "stairs up",
+        walkable: true,
+        transparent: true,
+        flammable: false,
+        shatters: false,
+        freezes: false,
+        opens_into: None,
+        closes_into: None,
+        locked: false,
+        falls_through: false,
+        hidden: false,
+        glyph: b'<',
+        color: rgb32!(190, 190, 190),
+      },
+      Terrain::DoorClosed => TerrainProperties {
+        name: "closed door",
+        walkable: false,
+        transparent: false,
+        flammable: true,
+        shatters: false,
+        freezes: false,
+        opens_into: Some(Terrain::DoorOpen),
+        closes_into: None,
+        locked: false,
+        falls_through: false,
+        hidden: false,
+        glyph: b'+',
+        color: rgb32!(185, 122, 87),
+      },
+      Terrain::DoorOpen => TerrainProperties {
+        name: "open door",
+        walkable: true,
+        transparent: true,
+        flammable: true,
+        shatters: false,
+        freezes: false,
+        opens_into: None,
+        closes_into: Some(Terrain::DoorClosed),
+        locked: false,
+        falls_through: false,
+        hidden: false,
+        glyph: b'\'',
+        color: rgb32!(185, 122, 87),
+      },
+      Terrain::DoorLocked => TerrainProperties {
+        name: "locked door",
+        walkable: false,
+        transparent: false,
+        flammable: false,
+        shatters: false,
+        freezes: false,
+        opens_into: Some(Terrain::DoorOpen),
+        closes_into: None,
+        locked: true,
+        falls_through: false,
+        hidden: false,
+        glyph: b'+',
+        color: rgb32!(160, 160, 175),
+      },
+      Terrain::Pit => TerrainProperties {
+        name: "pit",
+        walkable: true,
+        transparent: true,
+        flammable: false,
+        shatters: false,
+        freezes: false,
+        opens_into: None,
+        closes_into: None,
+        locked: false,
+        falls_through: true,
+        hidden: false,
+        glyph: b'O',
+        color: rgb32!(90, 70, 60),
+      },
+      Terrain::Trapdoor => TerrainProperties {
+        name: "floor",
+        walkable: true,
+        transparent: true,
+        flammable: false,
+        shatters: false,
+        freezes: false,
+        opens_into: None,
+        closes_into: None,
+        locked: false,
+        falls_through: true,
+        hidden: true,
+        glyph: b'.',
+        color: rgb32!(128, 128, 128),
+      },
     }
   }
+
+  pub fn is_walkable(self) -> bool {
+    self.properties().walkable
+  }
+
+  pub fn is_transparent(self) -> bool {
+    self.properties().transparent
+  }
+
+  /// If a creature could get through here, possibly by opening it first.
+  pub fn is_passable(self) -> bool {
+    let properties = self.properties();
+    properties.walkable || properties.opens_into.is_some()
+  }
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct CreatureID(pub usize);
 
+/// A single z layer of the world.
+///
+/// The terrain is stored densely, row by row, so lookups never need to hash
+/// anything. When serialized a level is its width, its height, all of the
+/// tiles in that same row order, then its staircases, and last of all how many
+/// turns the player has spent there and the last turn that they were there.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Level {
+  pub width: usize,
+  pub height: usize,
+  terrain: Vec<Terrain>,
+  pub stairs: Vec<Staircase>,
+  pub turns_spent: u32,
+  pub last_turn_here: u64,
+}
+
+impl Level {
+  /// Makes a level that's solid wall all the way through.
+  pub fn new(width: usize, height: usize) -> Self {
+    Level {
+      width,
+      height,
+      terrain: vec![Terrain::Wall; width * height],
+      stairs: vec![],
+      turns_spent: 0,
+      last_turn_here: 0,
+    }
+  }
+
+  /// Makes a level from the output of a map generator.
+  pub fn from_image(image: &VecImage<Terrain>) -> Self {
+    let mut out = Self::new(image.width(), image.height());
+    for (x, y, tile) in image.iter() {
+      out.terrain[y * out.width + x] = *tile;
+    }
+    out
+  }
+
+  fn index_of(&self, x: i32, y: i32) -> Option<usize> {
+    if x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height {
+      Some(y as usize * self.width + x as usize)
+    } else {
+      None
+    }
+  }
+
+  /// The terrain at the given position, or `None` if it's out of bounds.
+  pub fn get(&self, x: i32, y: i32) -> Option<Terrain> {
+    self.index_of(x, y).map(|index| self.terrain[index])
+  }
+
+  /// The terrain at the given position, with anything out of bounds counting
+  /// as a wall.
+  pub fn terrain(&self, x: i32, y: i32) -> Terrain {
+    self.get(x, y).unwrap_or(Terrain::Wall)
+  }
+
+  /// Changes the terrain at the given position. Out of bounds positions are
+  /// always walls, so setting them does nothing.
+  pub fn set(&mut self, x: i32, y: i32, terrain: Terrain) {
+    if let Some(index) = self.index_of(x, y) {
+      self.terrain[index] = terrain;
+    }
+  }
+}
+
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct GameWorld {
   pub player_location: Location,
@@ -344,14 +779,55 @@ pub struct GameWorld {
   pub creature_list: Vec<Creature>,
   pub creature_locations: HashMap<Location, CreatureID>,
   pub item_locations: HashMap<Location, Vec<Item>>,
-  pub terrain: HashMap<Location, Terrain>,
+  pub trap_locations: HashMap<Location, Trap>,
+  pub levels: BTreeMap<i32, Level>,
   pub gen: PCG32,
   pub deepest_depth: i32,
+  pub messages: Vec<String>,
+  /// How the game ended, `None` while it's still going.
+  pub outcome: Option<GameOutcome>,
+  /// The seed that the world was made from.
+  pub seed: u64,
+  /// How many monsters the player has killed.
+  pub kills: u32,
+  /// The deepest level that the player has set foot on.
+  pub deepest_reached: i32,
+  /// Where paged out levels go, paging is off when this is `None`.
+  pub level_page_dir: Option<::std::path::PathBuf>,
+  pub level_cache_limit: usize,
+  pub paged_out_levels: BTreeSet<i32>,
+  /// Levels in memory, from least to most recently visited.
+  pub recent_levels: Vec<i32>,
+  /// Monsters for every 10,000 cells of map on newly made levels.
+  pub monster_density: usize,
+  /// The level that the artifact gets put on when it's made.
+  pub artifact_depth: i32,
+  /// What got spawned onto each level made since the game started. The game
+  /// shows these on a debug key.
+  #[serde(skip)]
+  pub spawn_stats: BTreeMap<i32, SpawnStats>,
+  /// How many world turns have gone by since the game started.
+  pub turn_count: u64,
+  /// What happens on the levels that the player isn't on.
+  pub off_level_policy: OffLevelPolicy,
+  /// The generator that the next level's map gets made with.
+  pub next_map_gen: PCG32,
+  /// The next level's map, if a worker thread is already making it.
+  #[serde(skip)]
+  pregenerating: Option<PregeneratedMap>,
 }
-const GAME_DIMENSIONS: usize = 50;
+const MESSAGE_LOG_LIMIT: usize = 50;
+
+/// How many random rolls looking for floor happen before we just scan for it.
+pub const FLOOR_PICK_TRIES: usize = 5000;
 
 impl GameWorld {
   pub fn new(seed: u64) -> Self {
+    Self::try_new(seed).expect("Couldn't generate the first level!")
+  }
+
+  /// Makes a new world, or explains why the first level couldn't be made.
+  pub fn try_new(seed: u64) -> Result<Self, GenerationError> {
     // Make our world
     let mut out = Self {
       player_location: Location { x: 0, y: 0, z: 0 },
@@ -359,122 +835,204 @@ impl GameWorld {
       creature_list: vec![],
       creature_locations: HashMap::new(),
       item_locations: HashMap::new(),
-      terrain: HashMap::new(),
+      trap_locations: HashMap::new(),
+      levels: BTreeMap::new(),
       gen: PCG32::new(seed),
       deepest_depth: 1,
+      messages: vec![],
+      outcome: None,
+      seed,
+      kills: 0,
+      deepest_reached: 0,
+      level_page_dir: None,
+      level_cache_limit: DEFAULT_LEVEL_CACHE_LIMIT,
+      paged_out_levels: BTreeSet::new(),
+      recent_levels: vec![],
+      monster_density: DEFAULT_MONSTER_DENSITY,
+      artifact_depth: ARTIFACT_DEPTH,
+      spawn_stats: BTreeMap::new(),
+      turn_count: 0,
+      off_level_policy: OffLevelPolicy::default(),
+      next_map_gen: PCG32::default(),
+      pregenerating: None,
     };
+    out.next_map_gen = out.gen.fork();
 
     // Generate the player
     let mut player = Creature::new_player(out.next_creature_id);
     out.next_creature_id += 1;
 
     // Add the first z-layer.
-    out.add_z_layer(None);
+    out.add_z_layer()?;
 
-    // Place the Player
-    let player_start = out.pick_random_floor(out.deepest_depth);
+    // Place the Player at the way out, or well away from the monsters if
+    // something's already standing there
+    let player_start = match out.exit_location() {
+      Some(exit) if !out.creature_locations.contains_key(&exit) => exit,
+      _ => out.pick_floor_with(
+        out.deepest_depth,
+        &[PlacementRule::NoCreatureWithin(0), PlacementRule::NoCreatureWithin(ARRIVAL_SAFE_DISTANCE)],
+        1,
+      )?,
+    };
     let player_id = player.id.0;
     out.creature_list.push(player);
     out.creature_locations.insert(player_start, CreatureID(player_id));
     out.player_location = player_start;
+    out.deepest_reached = player_start.z;
+    out.recent_levels.push(player_start.z);
 
-    out
+    Ok(out)
   }
 
-  pub fn add_z_layer(&mut self, down_stairs: Option<Location>) {
-    self.deepest_depth -= 1;
-
-    // Generate a new z layer, with optional constraint
-    let caves: VecImage<bool> = match down_stairs {
-      None => make_cellular_caves(GAME_DIMENSIONS, GAME_DIMENSIONS, &mut self.gen),
-      Some(stairs) => 'cave: loop {
-        let potential = make_cellular_caves(GAME_DIMENSIONS, GAME_DIMENSIONS, &mut self.gen);
-        let center = (stairs.x as usize, stairs.y as usize);
-        for xy in Some(center)
-          .into_iter()
-          .chain(stairs.neighbors().map(|loc| (loc.x as usize, loc.y as usize)))
-        {
-          if !potential[xy] {
-            break 'cave potential;
-          }
-        }
-      },
-    };
+  /// Makes a new level under the deepest one.
+  ///
+  /// The level above has to be in memory, so that its stairs down can be
+  /// linked to the new level's stairs up. If the map itself can't be made then
+  /// nothing changes, but an error after that leaves the new level in place
+  /// with some of its contents missing.
+  pub fn add_z_layer(&mut self) -> Result<(), GenerationError> {
+    let z = self.deepest_depth - 1;
+
+    // Generate a new z layer, or finish up the one that's already coming
+    let map: GeneratedMap = self.take_level_map(z)?;
+    let (width, height) = (map.terrain.width(), map.terrain.height());
 
     // Place the Terrain
-    for (x, y, tile) in caves.iter() {
-      self.terrain.insert(
-        Location {
-          x: x as i32,
-          y: y as i32,
-          z: self.deepest_depth,
-        },
-        if *tile { Terrain::Wall } else { Terrain::Floor },
-      );
-    }
-    // Add the stairs back up, if necessary
-    down_stairs.map(|loc| self.terrain.insert(loc + Location { x: 0, y: 0, z: -1 }, Terrain::StairsUp));
-    // Add some stairs even deeper
-    let stairs_place = self.pick_random_floor(self.deepest_depth);
-    self.terrain.insert(stairs_place, Terrain::StairsDown);
-
-    // Place the Creatures
-    for _ in 0..(GAME_DIMENSIONS / 2) {
-      let monster = Creature::new_kestrel(self.next_creature_id, self.deepest_depth);
-      self.next_creature_id += 1;
-      let monster_id = monster.id.0;
-      let monster_start = self.pick_random_floor(self.deepest_depth);
-      match self.creature_locations.entry(monster_start) {
-        Entry::Occupied(_) => {
-          // if we happen to pick an occupied location, just don't add a
-          // creature for this pass of the loop.
-          continue;
-        }
-        Entry::Vacant(ve) => {
-          self.creature_list.push(monster);
-          ve.insert(CreatureID(monster_id));
-        }
-      }
-    }
+    let to_location = |&(x, y): &(usize, usize)| Location { x: x as i32, y: y as i32, z };
+    let mut level = Level::from_image(&map.terrain);
+    level.last_turn_here = self.turn_count;
+    self.levels.insert(z, level);
+    self.deepest_depth = z;
+    // Link up with the level above, and add some stairs even deeper
+    self.place_stairs(z, &map.hints)?;
+
+    // Look over the layout, to find spots worth guarding and hiding things in
+    let analysis = self.analyze_level(z).ok_or(GenerationError::NoFloor { z })?;
+    let to_floor_location = |(x, y): (i32, i32)| Some(Location { x, y, z }).filter(|loc| self.terrain_at(*loc) == Terrain::Floor);
+    let guard_posts: Vec<Location> = analysis.chokepoints().filter_map(to_floor_location).collect();
+    let mut hiding_spots: Vec<Location> = analysis.dead_ends().filter_map(to_floor_location).collect();
+
+    // Place the Creatures, some of them standing guard over the chokepoints
+    let spawn_stats = self.populate_level(z, &map.hints, guard_posts)?;
+    self.spawn_stats.insert(z, spawn_stats);
 
     // Figure out what the chances of a random item dropping are.
-    let depth_u32 = self.deepest_depth.abs() as u32;
+    let depth_u32 = z.unsigned_abs();
     let mut item_frequencies = FrequencyChart::new(Item::PotionStrength, depth_u32);
     item_frequencies.push_item(Item::PotionHealth, depth_u32 + 20);
     item_frequencies.push_item(Item::BombBlast, depth_u32);
     item_frequencies.push_item(Item::BombIce, depth_u32.saturating_sub(10));
+    item_frequencies.push_item(Item::FoodRock, 6);
+    item_frequencies.push_item(Item::FoodSoup, depth_u32 / 4 + 2);
     item_frequencies.push_item(Item::Dagger(0), depth_u32 + 5);
     item_frequencies.push_item(Item::Warhammer(0), 2 * depth_u32);
     item_frequencies.push_item(Item::Fernweave(0), depth_u32 / 2 + 5);
     item_frequencies.push_item(Item::LobsterMail(0), depth_u32);
     item_frequencies.push_item(Item::CrystalPlate(0), depth_u32);
+    for &(item, weight) in theme_for_depth(z).definition().extra_items.iter() {
+      item_frequencies.push_item(item, weight);
+    }
+
+    // Place any keys that the level needs, somewhere outside of the locked
+    // room if their spot isn't floor anymore
+    for mut key_spot in map.hints.key_spots.iter().map(to_location) {
+      if self.terrain_at(key_spot) != Terrain::Floor {
+        let mut rules: Vec<PlacementRule> = map.hints.locked_room.map(PlacementRule::OutsideRect).into_iter().collect();
+        rules.push(PlacementRule::Unclaimed);
+        key_spot = self.pick_floor_with(z, &rules, rules.len() - 1)?;
+      }
+      self.item_locations.entry(key_spot).or_default().push(Item::Key);
+    }
+
+    // The artifact goes as far from the way in as it can
+    if z == self.artifact_depth {
+      self.place_artifact(z)?;
+    }
 
-    // Place the Items
-    for _ in 0..GAME_DIMENSIONS {
-      let item_spot = self.pick_random_floor(self.deepest_depth);
+    // Place the Items, vault treasure first, and then the rest one to a tile,
+    // some of them tucked away at the ends of dead ends
+    for item_spot in map.hints.item_spots.iter().map(to_location) {
+      let new_item = item_frequencies.roll_with(&mut self.gen);
+      self.item_locations.entry(item_spot).or_default().push(new_item);
+    }
+    let item_placement = self.placement(z, &[PlacementRule::Unclaimed])?;
+    for _ in 0..(width * height / 50) {
+      let item_spot = if !hiding_spots.is_empty() && self.gen.next_u32() >> 30 == 0 {
+        let hiding_index = roll_between(&mut self.gen, 0, hiding_spots.len() - 1);
+        let hiding_spot = hiding_spots.swap_remove(hiding_index);
+        if !item_placement.allows(self, hiding_spot) {
+          continue;
+        }
+        hiding_spot
+      } else {
+        self.pick_floor_for(&item_placement)?
+      };
       let new_item = item_frequencies.roll_with(&mut self.gen);
-      self.item_locations.entry(item_spot).or_insert(Vec::new()).push(new_item);
-    }
-  }
-
-  pub fn pick_random_floor(&mut self, z: i32) -> Location {
-    let indexer = RandRangeInclusive32::new(0..=(GAME_DIMENSIONS as u32 - 1));
-    let mut tries = 0;
-    let mut x = indexer.roll_with(&mut self.gen) as usize;
-    let mut y = indexer.roll_with(&mut self.gen) as usize;
-    let mut loc = Location { x: x as i32, y: y as i32, z };
-    while self.terrain[&loc] != Terrain::Floor {
-      x = indexer.roll_with(&mut self.gen) as usize;
-      y = indexer.roll_with(&mut self.gen) as usize;
-      loc = Location { x: x as i32, y: y as i32, z };
-      if tries > 5000 {
-        panic!("couldn't find a floor tile!");
+      self.item_locations.entry(item_spot).or_default().push(new_item);
+    }
+
+    // Hide some traps
+    self.place_traps(z)?;
+    debug_assert_eq!(self.verify_stair_links(), Ok(()));
+
+    // Get started on the level after this one
+    self.start_pregenerating(z - 1);
+    Ok(())
+  }
+
+  /// Picks a random floor tile on the given level.
+  ///
+  /// If that many rolls don't turn one up this takes the first floor tile in
+  /// row order instead, so it can't spin forever on a level that's nearly
+  /// solid rock. It's only an error if there's no floor at all.
+  pub fn pick_random_floor(&mut self, z: i32) -> Result<Location, GenerationError> {
+    let (width, height) = match self.levels.get(&z) {
+      Some(level_ref) if level_ref.width > 0 && level_ref.height > 0 => (level_ref.width, level_ref.height),
+      _ => return Err(GenerationError::NoFloor { z }),
+    };
+    let x_indexer = RandRangeInclusive32::new(0..=(width as u32 - 1));
+    let y_indexer = RandRangeInclusive32::new(0..=(height as u32 - 1));
+    for _tries in 0..FLOOR_PICK_TRIES {
+      let x = x_indexer.roll_with(&mut self.gen) as i32;
+      let y = y_indexer.roll_with(&mut self.gen) as i32;
+      let loc = Location { x, y, z };
+      if self.terrain_at(loc) == Terrain::Floor {
+        return Ok(loc);
       }
     }
-    loc
+    self.first_floor_where(z, |_| true)
+  }
+
+  /// Picks a random floor tile on the given level that nobody is standing on.
+  pub fn pick_open_floor(&mut self, z: i32) -> Result<Location, GenerationError> {
+    self.pick_floor_with(z, &[PlacementRule::NoCreatureWithin(0)], 1)
+  }
+
+  /// The first floor tile in row order that passes the check.
+  fn first_floor_where<F>(&self, z: i32, check: F) -> Result<Location, GenerationError>
+  where
+    F: Fn(Location) -> bool,
+  {
+    let level_ref = self.levels.get(&z).ok_or(GenerationError::NoFloor { z })?;
+    for y in 0..level_ref.height as i32 {
+      for x in 0..level_ref.width as i32 {
+        let loc = Location { x, y, z };
+        if level_ref.terrain(x, y) == Terrain::Floor && check(loc) {
+          return Ok(loc);
+        }
+      }
+    }
+    Err(GenerationError::NoFloor { z })
   }
 
   pub fn move_player(&mut self, delta: Location) {
+    let player_hunger = self.creature_list.iter().find(|creature_ref| creature_ref.is_the_player).unwrap().hunger_state();
+    if player_hunger == HungerState::Fainting && self.gen.next_u32() >> 30 == 0 {
+      self.push_message("You faint from hunger!".to_string());
+      self.run_world_turn();
+      return;
+    }
     let player_move_target = self.player_location + delta;
     match self.creature_locations.get(&player_move_target) {
       Some(target_id_ref) => {
@@ -482,7 +1040,7 @@ impl GameWorld {
         let player_damage_roll = {
           let player_id_ref = self.creature_locations.get(&self.player_location).unwrap();
           let player_ref = self.creature_list.iter().find(|creature_ref| &creature_ref.id == player_id_ref).unwrap();
-          step(&mut self.gen, player_ref.damage_step)
+          step(&mut self.gen, player_ref.damage_step - player_hunger.damage_penalty())
         };
         let target_ref_mut = self
           .creature_list
@@ -494,46 +1052,99 @@ impl GameWorld {
       }
       None => {
         // no one is there, move
-        match *self.terrain.entry(player_move_target).or_insert(Terrain::Floor) {
-          Terrain::Wall | Terrain::Ice => {
-            // Accidentally bumping a wall doesn't consume a turn.
-            return;
-          }
-          Terrain::Floor | Terrain::StairsDown | Terrain::StairsUp => {
-            let player_id = self
-              .creature_locations
-              .remove(&self.player_location)
-              .expect("The player wasn't where they should be!");
-            let old_creature = self.creature_locations.insert(player_move_target, player_id);
-            debug_assert!(old_creature.is_none());
-            self.player_location = player_move_target;
-            // grab items that are here, if any
-            let player_id_ref = self.creature_locations.get(&self.player_location).unwrap();
-            let player_mut = self
-              .creature_list
-              .iter_mut()
-              .find(|creature_mut| &creature_mut.id == player_id_ref)
-              .unwrap();
-            let floor_items = self.item_locations.entry(self.player_location).or_insert(Vec::new());
-            player_mut.inventory.append(floor_items);
+        let target_properties = self.terrain_at(player_move_target).properties();
+        if !target_properties.walkable {
+          match target_properties.opens_into {
+            Some(opened) if target_properties.locked => {
+              let player_mut = self.creature_list.iter_mut().find(|creature_ref| creature_ref.is_the_player).unwrap();
+              match player_mut.inventory.iter().position(|&item| item == Item::Key) {
+                Some(key_index) => {
+                  player_mut.inventory.remove(key_index);
+                  self.set_terrain(player_move_target, opened);
+                  self.push_message("You unlock the door.".to_string());
+                }
+                None => {
+                  self.push_message("The door is locked.".to_string());
+                  return;
+                }
+              }
+            }
+            Some(opened) => self.set_terrain(player_move_target, opened),
+            None => {
+              // Accidentally bumping a wall doesn't consume a turn.
+              return;
+            }
           }
+          self.run_world_turn();
+          return;
+        }
+        // grab items that are here, if any, then watch your step
+        self.relocate_player(player_move_target);
+        self.spring_trap();
+        if self.terrain_at(self.player_location).properties().falls_through {
+          self.fall_through_floor(self.player_location);
         }
       }
     }
     self.run_world_turn();
   }
 
+  /// Takes the stairs the player is standing on, going down if `floor_delta`
+  /// is negative and up if it's positive. Going up the stairs on the top level
+  /// leaves the dungeon.
   pub fn change_floor(&mut self, floor_delta: i32) {
-    let player_terrain = self.terrain[&self.player_location];
-    match (player_terrain, floor_delta) {
-      (Terrain::StairsDown, -1) => {
-        if self.player_location.z == self.deepest_depth {
-          self.add_z_layer(Some(self.player_location));
+    let here = self.player_location;
+    let wanted_terrain = if floor_delta < 0 { Terrain::StairsDown } else { Terrain::StairsUp };
+    if floor_delta == 0 || self.terrain_at(here) != wanted_terrain {
+      return;
+    }
+    let linked_destination = |world: &Self| {
+      world
+        .levels
+        .get(&here.z)
+        .and_then(|level_ref| level_ref.staircase_at(here.x, here.y))
+        .and_then(|stairs_ref| stairs_ref.destination)
+    };
+    if linked_destination(self).is_none() && floor_delta > 0 && here.z == TOP_LEVEL_Z {
+      self.try_to_escape();
+      return;
+    }
+    if linked_destination(self).is_none() && floor_delta < 0 && here.z == self.deepest_depth {
+      if let Err(e) = self.add_z_layer() {
+        self.push_message(format!("Couldn't generate level {}: {}", here.z - 1, e));
+        return;
+      }
+    }
+    let destination = match linked_destination(self) {
+      Some(destination) => destination,
+      None => return,
+    };
+    if let Err(e) = self.touch_level(destination.z) {
+      self.push_message(format!("Couldn't load level {}: {}", destination.z, e));
+      return;
+    }
+    if self.creature_locations.contains_key(&destination) {
+      self.push_message("Something is blocking the other end of the stairs.".to_string());
+      return;
+    }
+    self.relocate_player(destination);
+    self.run_world_turn();
+  }
+
+  /// Closes the door next to the player, if there's nothing in the way.
+  pub fn close_door(&mut self, delta: Location) {
+    let target = self.player_location + delta;
+    match self.terrain_at(target).properties().closes_into {
+      Some(closed) => {
+        let blocked = self.creature_locations.contains_key(&target) || self.item_locations.get(&target).map(|v| !v.is_empty()).unwrap_or(false);
+        if blocked {
+          self.push_message("Something is in the way.".to_string());
+        } else {
+          self.set_terrain(target, closed);
+          self.run_world_turn();
         }
-        self.move_player(Location { x: 0, y: 0, z: -1 })
       }
-      (Terrain::StairsUp, 1) => self.move_player(Location { x: 0, y: 0, z: 1 }),
-      _ => {}
+      None => self.push_message("There's nothing there to close.".to_string()),
     }
   }
 
@@ -554,6 +1165,18 @@ impl GameWorld {
           Item::PotionHealth | Item::PotionStrength => {
             apply_potion(&item, player_mut, &mut self.gen);
           }
+          Item::FoodRock | Item::FoodSoup => {
+            player_mut.nutrition = (player_mut.nutrition + item.nutrition_value()).min(MAX_NUTRITION);
+          }
+          Item::Key => {
+            // keys get used up by walking into locked doors.
+            self.push_message("Walk into a locked door to use a key.".to_string());
+            return UseItemResult::ItemUsed;
+          }
+          Item::Artifact => {
+            self.push_message(format!("The {} hums in your hands. Take it back up to the surface!", ARTIFACT_NAME));
+            return UseItemResult::ItemUsed;
+          }
           Item::CrystalPlate(_) | Item::Fernweave(_) | Item::LobsterMail(_) => {
             player_mut.equipped_armor.take().map(|old_armor| player_mut.inventory.push(old_armor));
             player_mut.equipped_armor = Some(item);
@@ -600,13 +1223,17 @@ impl GameWorld {
         ppfov(
           (blast_center.x, blast_center.y),
           2,
-          |x, y| self.terrain[&Location { x, y, z }] == Terrain::Wall,
+          |x, y| {
+            let properties = self.terrain_at(Location { x, y, z }).properties();
+            !properties.transparent && !properties.shatters && !properties.flammable
+          },
           |x, y| blast_locations.push(Location { x, y, z }),
         );
         let mut blast_targets = vec![];
         for location in blast_locations.into_iter() {
-          if *self.terrain.entry(location).or_insert(Terrain::Wall) == Terrain::Ice {
-            *self.terrain.entry(location).or_insert(Terrain::Wall) = Terrain::Floor;
+          let properties = self.terrain_at(location).properties();
+          if properties.shatters || properties.flammable {
+            self.set_terrain(location, Terrain::Floor);
           }
           match self.creature_locations.get(&location) {
             None => {}
@@ -631,15 +1258,23 @@ impl GameWorld {
           |_, _| false, /* vision check doesn't matter on radius 1 fov */
           |x, y| blast_locations.push(Location { x, y, z }),
         );
+        let mut frozen_experience = 0;
         for location in blast_locations.into_iter() {
-          if *self.terrain.entry(location).or_insert(Terrain::Wall) == Terrain::Floor {
-            *self.terrain.entry(location).or_insert(Terrain::Wall) = Terrain::Ice;
-            self.item_locations.entry(location).or_insert(Vec::new()).clear();
+          if self.terrain_at(location).properties().freezes {
+            self.set_terrain(location, Terrain::Ice);
+            self.item_locations.entry(location).or_default().retain(|item| matches!(item, Item::Artifact | Item::Key));
             let removed_cid = self.creature_locations.remove(&location);
             // this is a hacky way to never delete the player on accident, but
             // not really any _more_ hacky than the rest of the codebase.
             removed_cid.map(|cid_ref| {
               if cid_ref.0 > 1 {
+                self.kills += 1;
+                frozen_experience += self
+                  .creature_list
+                  .iter()
+                  .find(|creature_ref| creature_ref.id == cid_ref)
+                  .map(|creature_ref| creature_ref.experience_value)
+                  .unwrap_or(0);
                 self.creature_list.retain(|creature_ref| &creature_ref.id != &cid_ref);
               } else {
                 self.creature_locations.insert(location, CreatureID(cid_ref.0));
@@ -647,6 +1282,7 @@ impl GameWorld {
             });
           }
         }
+        self.gain_experience(frozen_experience);
       }
       Some(other) => panic!("Item was not an item that can target: {}", other),
       None => panic!("No such item letter: {}", item_letter),
@@ -662,12 +1298,146 @@ impl GameWorld {
     self.run_world_turn();
   }
 
+  /// The terrain at a location, anything outside of the levels is wall.
+  pub fn terrain_at(&self, loc: Location) -> Terrain {
+    self.levels.get(&loc.z).map(|level_ref| level_ref.terrain(loc.x, loc.y)).unwrap_or(Terrain::Wall)
+  }
+
+  pub fn set_terrain(&mut self, loc: Location, terrain: Terrain) {
+    if let Some(level_mut) = self.levels.get_mut(&loc.z) {
+      level_mut.set(loc.x, loc.y, terrain);
+    }
+  }
+
+  /// Checks that nothing stands between `from` and `to` on their shared level.
+  fn has_line_of_fire(&self, from: Location, to: Location) -> bool {
+    let z = from.z;
+    line_of_fire((from.x, from.y), (to.x, to.y), |x, y| {
+      let here = Location { x, y, z };
+      !self.terrain_at(here).is_transparent() || self.creature_locations.contains_key(&here)
+    })
+  }
+
+  fn make_ranged_attack(&mut self, attacker_id: &CreatureID, ranged: RangedAttack) {
+    let attacker_mut = self
+      .creature_list
+      .iter_mut()
+      .find(|creature_mut_ref| &creature_mut_ref.id == attacker_id)
+      .unwrap();
+    attacker_mut.ammo -= 1;
+    let attacker_kind = attacker_mut.kind.expect("only monsters make ranged attacks");
+    let damage_roll = step(&mut self.gen, ranged.damage_step);
+    println!("{:?} {} the player for {} damage", attacker_id, ranged.verb, damage_roll);
+    self.damage_player(damage_roll, DeathCause::Slain(attacker_kind));
+  }
+
+  /// Hurts the player, remembering what did them in if they die from it.
+  fn damage_player(&mut self, amount: i32, cause: DeathCause) {
+    let player_mut = self.creature_list.iter_mut().find(|creature_ref| creature_ref.is_the_player).unwrap();
+    player_mut.hit_points -= amount;
+    if player_mut.hit_points < 1 && self.outcome.is_none() {
+      self.outcome = Some(GameOutcome::Died(cause));
+      self.push_message(format!("You were {}.", cause));
+    }
+  }
+
+  /// Makes the player a little hungrier, with a warning as they cross each
+  /// hunger threshold.
+  fn tick_hunger(&mut self) {
+    let (old_state, new_state, starved) = {
+      let player_mut = self.creature_list.iter_mut().find(|creature_ref| creature_ref.is_the_player).unwrap();
+      let old_state = player_mut.hunger_state();
+      player_mut.nutrition -= 1;
+      (old_state, player_mut.hunger_state(), player_mut.nutrition <= 0)
+    };
+    if starved {
+      let remaining_hit_points = self.creature_list.iter().find(|creature_ref| creature_ref.is_the_player).unwrap().hit_points;
+      self.damage_player(remaining_hit_points.max(1), DeathCause::Starvation);
+    } else if new_state > old_state {
+      self.push_message(match new_state {
+        HungerState::Hungry => "You are getting hungry.".to_string(),
+        HungerState::Weak => "You feel weak from hunger.".to_string(),
+        _ => "You are fainting from hunger! Eat something!".to_string(),
+      });
+    }
+  }
+
+  /// The open neighbor of `loc` that gets a monster furthest from the player.
+  fn retreat_step(&self, loc: Location) -> Option<Location> {
+    let current_distance = loc.manhattan_distance(self.player_location);
+    loc
+      .neighbors()
+      .filter(|neighbor| {
+        self.terrain_at(*neighbor).is_walkable() && !self.creature_locations.contains_key(neighbor) && !self.monster_avoids(*neighbor)
+      })
+      .filter(|neighbor| neighbor.manhattan_distance(self.player_location) > current_distance)
+      .max_by_key(|neighbor| neighbor.manhattan_distance(self.player_location))
+  }
+
+  /// The next step that a monster at `from` should take to get to `to`,
+  /// going around any traps.
+  fn path_step(&self, from: Location, to: Location) -> Option<Location> {
+    let level_ref = &self.levels[&from.z];
+    let can_path = |loc: Location| {
+      let terrain = level_ref.terrain(loc.x, loc.y);
+      (terrain.is_walkable() || (terrain.is_passable() && !terrain.properties().locked)) && !self.monster_avoids(loc)
+    };
+    a_star(to, from, can_path).and_then(|path| {
+      debug_assert_eq!(from, path[0]);
+      path.get(1).cloned()
+    })
+  }
+
+  /// Adds a message to the log, dropping the oldest if the log is full.
+  pub fn push_message(&mut self, message: String) {
+    self.messages.push(message);
+    if self.messages.len() > MESSAGE_LOG_LIMIT {
+      self.messages.remove(0);
+    }
+  }
+
+  /// Gives the player experience, leveling them up as many times as needed.
+  fn gain_experience(&mut self, amount: u32) {
+    if amount == 0 {
+      return;
+    }
+    let mut level_ups = vec![];
+    {
+      let player_mut = self.creature_list.iter_mut().find(|creature_ref| creature_ref.is_the_player).unwrap();
+      player_mut.experience += amount;
+      while player_mut.experience >= experience_to_reach(player_mut.character_level + 1) {
+        player_mut.character_level += 1;
+        player_mut.max_hit_points += 5;
+        player_mut.hit_points += 5;
+        player_mut.damage_step += 1;
+        level_ups.push(player_mut.character_level);
+      }
+    }
+    for new_level in level_ups {
+      self.push_message(format!("You feel stronger! Welcome to level {}.", new_level));
+    }
+  }
+
   pub fn run_world_turn(&mut self) {
+    self.tick_hunger();
+    self.turn_count += 1;
+    let player_z = self.player_location.z;
+    if let Some(level_mut) = self.levels.get_mut(&player_z) {
+      level_mut.turns_spent += 1;
+      level_mut.last_turn_here = self.turn_count;
+    }
+    // Only the player's level gets a turn, the rest wait for `catch_up_level`.
+    let here: HashSet<usize> = self
+      .creature_locations
+      .iter()
+      .filter(|&(loc, _)| loc.z == player_z)
+      .map(|(_, cid)| cid.0)
+      .collect();
     let initiative_list: Vec<CreatureID> = self
       .creature_list
       .iter()
       .filter_map(|creature_mut| {
-        if creature_mut.is_the_player || creature_mut.hit_points < 1 {
+        if creature_mut.is_the_player || creature_mut.hit_points < 1 || !here.contains(&creature_mut.id.0) {
           None
         } else {
           Some(CreatureID(creature_mut.id.0))
@@ -687,30 +1457,68 @@ impl GameWorld {
         Some(loc) => {
           // Look around
           let seen_locations = {
-            let terrain_ref = &self.terrain;
+            let level_ref = &self.levels[&loc.z];
             let mut seen_locations = HashSet::new();
             let z = loc.z;
             ppfov(
               (loc.x, loc.y),
               7,
-              |x, y| {
-                let here = *terrain_ref.get(&Location { x, y, z }).unwrap_or(&Terrain::Wall);
-                here == Terrain::Wall || here == Terrain::Ice
-              },
+              |x, y| !level_ref.terrain(x, y).is_transparent(),
               |x, y| {
                 seen_locations.insert(Location { x, y, z });
               },
             );
             seen_locations
           };
+          // Shoot from a distance if we're able to
+          let sees_player = seen_locations.contains(&self.player_location);
+          let distance_to_player = loc.manhattan_distance(self.player_location);
+          let (ranged_attack, preferred_distance, alerted_to) = {
+            let creature_ref = self
+              .creature_list
+              .iter()
+              .find(|creature_ref| &creature_ref.id == creature_id_ref)
+              .unwrap();
+            (
+              creature_ref.ready_ranged_attack(),
+              creature_ref.kind.map(|kind| kind.definition().preferred_distance).unwrap_or(0),
+              creature_ref.alerted_to,
+            )
+          };
+          if let Some(ranged) = ranged_attack {
+            if sees_player && distance_to_player > 1 && distance_to_player <= ranged.range && self.has_line_of_fire(loc, self.player_location) {
+              self.make_ranged_attack(creature_id_ref, ranged);
+              continue;
+            }
+          }
           // Decide where to go
-          let move_target = if seen_locations.contains(&self.player_location) {
-            let terrain_ref = &self.terrain;
-            let path = a_star(self.player_location, loc, |loc| {
-              terrain_ref.get(&loc).unwrap_or(&Terrain::Wall) != &Terrain::Wall
-            }).expect("couldn't find a path");
-            debug_assert_eq!(loc, path[0]);
-            path[1]
+          let retreat_target = if sees_player && ranged_attack.is_some() && distance_to_player < preferred_distance {
+            self.retreat_step(loc)
+          } else {
+            None
+          };
+          let move_target = if let Some(retreat) = retreat_target {
+            retreat
+          } else if sees_player {
+            match self.path_step(loc, self.player_location) {
+              Some(next) => next,
+              // we can see them but there's no way over there
+              None => continue,
+            }
+          } else if let Some(noise) = alerted_to {
+            // go see what the noise was, and stop caring once we're there
+            match self.path_step(loc, noise) {
+              Some(next) if loc.manhattan_distance(noise) > 1 => next,
+              _ => {
+                self
+                  .creature_list
+                  .iter_mut()
+                  .find(|creature_mut| &creature_mut.id == creature_id_ref)
+                  .unwrap()
+                  .alerted_to = None;
+                continue;
+              }
+            }
           } else {
             loc + match self.gen.next_u32() >> 30 {
               0 => Location { x: 0, y: 1, z: 0 },
@@ -724,40 +1532,64 @@ impl GameWorld {
           match self.creature_locations.get(&move_target) {
             Some(target_id_ref) => {
               // someone is there, do the attack!
-              let creature_damage_roll = {
+              let (creature_damage_roll, creature_kind) = {
                 let creature_ref = self
                   .creature_list
                   .iter()
                   .find(|creature_ref| &creature_ref.id == creature_id_ref)
                   .unwrap();
-                step(&mut self.gen, creature_ref.damage_step)
+                (step(&mut self.gen, creature_ref.damage_step), creature_ref.kind)
               };
-              let target_ref_mut = self
+              let target_is_the_player = self
                 .creature_list
-                .iter_mut()
-                .find(|creature_mut_ref| &creature_mut_ref.id == target_id_ref)
-                .unwrap();
-              if target_ref_mut.is_the_player {
-                target_ref_mut.hit_points -= creature_damage_roll;
+                .iter()
+                .find(|creature_ref| &creature_ref.id == target_id_ref)
+                .unwrap()
+                .is_the_player;
+              if target_is_the_player {
                 println!("{:?} did {} damage to {:?}", creature_id_ref, creature_damage_roll, target_id_ref);
+                self.damage_player(
+                  creature_damage_roll,
+                  DeathCause::Slain(creature_kind.expect("only monsters take turns")),
+                );
               }
               // TODO: log that we did damage.
             }
-            None => match *self.terrain.entry(move_target).or_insert(Terrain::Floor) {
-              Terrain::Wall | Terrain::Ice => {
-                continue;
-              }
-              Terrain::Floor | Terrain::StairsDown | Terrain::StairsUp => {
+            None => {
+              let target_properties = self.terrain_at(move_target).properties();
+              if self.monster_avoids(move_target) {
+                // monsters know better than to walk onto their own traps.
+              } else if target_properties.walkable {
                 let id = self.creature_locations.remove(&loc).expect("The creature wasn't where they should be!");
                 let old_id = self.creature_locations.insert(move_target, id);
                 debug_assert!(old_id.is_none());
+                if target_properties.falls_through {
+                  self.fall_through_floor(move_target);
+                }
+              } else if let Some(opened) = target_properties.opens_into {
+                // monsters know how to open doors, but they don't carry keys.
+                if !target_properties.locked {
+                  self.set_terrain(move_target, opened);
+                }
               }
-            },
+            }
           }
         }
       }
     }
-    // End Phase, we clear any dead NPCs off the list.
+    // End Phase, we clear any dead NPCs off the list. Anything that was worth
+    // experience counts as one of the player's kills.
+    self.kills += self
+      .creature_list
+      .iter()
+      .filter(|creature_ref| !creature_ref.is_the_player && creature_ref.hit_points < 1 && creature_ref.experience_value > 0)
+      .count() as u32;
+    let experience_earned: u32 = self
+      .creature_list
+      .iter()
+      .filter(|creature_ref| !creature_ref.is_the_player && creature_ref.hit_points < 1)
+      .map(|creature_ref| creature_ref.experience_value)
+      .sum();
     let creature_locations_mut = &mut self.creature_locations;
     self.creature_list.retain(|creature_ref| {
       let keep = creature_ref.hit_points > 0 || creature_ref.is_the_player;
@@ -771,6 +1603,9 @@ impl GameWorld {
       };
       keep
     });
+    self.gain_experience(experience_earned);
+    // Something new might have wandered in while all that was going on.
+    self.spawn_wanderers();
   }
 }
 
@@ -0,0 +1,64 @@
+//! Straight lines across the grid, for things that fly.
+
+/// All the grid cells on the line from `start` to `end`, in order.
+///
+/// Uses Bresenham's algorithm, so the line is symmetric only up to
+/// tie-breaking. Both endpoints are included.
+pub fn bresenham_line((x0, y0): (i32, i32), (x1, y1): (i32, i32)) -> Vec<(i32, i32)> {
+  let dx = (x1 - x0).abs();
+  let dy = -(y1 - y0).abs();
+  let sx = if x0 < x1 { 1 } else { -1 };
+  let sy = if y0 < y1 { 1 } else { -1 };
+  let mut err = dx + dy;
+  let (mut x, mut y) = (x0, y0);
+  let mut out = vec![(x, y)];
+  while (x, y) != (x1, y1) {
+    let e2 = 2 * err;
+    if e2 >= dy {
+      err += dy;
+      x += sx;
+    }
+    if e2 <= dx {
+      err += dx;
+      y += sy;
+    }
+    out.push((x, y));
+  }
+  out
+}
+
+/// Checks if a projectile could travel from `start` to `end`.
+///
+/// Only the cells strictly between the two endpoints are tested with
+/// `blocked`, so the shooter and the target never block themselves.
+pub fn line_of_fire<B>(start: (i32, i32), end: (i32, i32), blocked: B) -> bool
+where
+  B: Fn(i32, i32) -> bool,
+{
+  let line = bresenham_line(start, end);
+  line.iter().skip(1).take(line.len().saturating_sub(2)).all(|&(x, y)| !blocked(x, y))
+}
+
+#[test]
+fn bresenham_line_tests() {
+  assert_eq!(bresenham_line((0, 0), (0, 0)), vec![(0, 0)]);
+  assert_eq!(bresenham_line((0, 0), (3, 0)), vec![(0, 0), (1, 0), (2, 0), (3, 0)]);
+  assert_eq!(bresenham_line((0, 0), (-2, -2)), vec![(0, 0), (-1, -1), (-2, -2)]);
+  let long_line = bresenham_line((1, 2), (8, -3));
+  assert_eq!(long_line[0], (1, 2));
+  assert_eq!(*long_line.last().unwrap(), (8, -3));
+  for pair in long_line.windows(2) {
+    assert!((pair[0].0 - pair[1].0).abs() <= 1);
+    assert!((pair[0].1 - pair[1].1).abs() <= 1);
+  }
+}
+
+#[test]
+fn line_of_fire_tests() {
+  // a wall in the middle blocks things
+  assert!(!line_of_fire((0, 0), (4, 0), |x, y| (x, y) == (2, 0)));
+  // but walls at the ends do not
+  assert!(line_of_fire((0, 0), (4, 0), |x, y| (x, y) == (0, 0) || (x, y) == (4, 0)));
+  // and adjacent cells are always in the line of fire
+  assert!(line_of_fire((0, 0), (1, 1), |_, _| true));
+}
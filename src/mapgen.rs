@@ -0,0 +1,761 @@
+//! Level generators, and the schedule of which one builds which depth.
+
+use super::*;
+
+/// A rectangle of map cells, used for rooms and partitions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+  pub x: usize,
+  pub y: usize,
+  pub width: usize,
+  pub height: usize,
+}
+
+impl Rect {
+  pub fn center(&self) -> (usize, usize) {
+    (self.x + self.width / 2, self.y + self.height / 2)
+  }
+
+  /// If the rectangles overlap or are closer than `padding` cells apart.
+  pub fn near(&self, other: &Rect, padding: usize) -> bool {
+    self.x < other.x + other.width + padding
+      && other.x < self.x + self.width + padding
+      && self.y < other.y + other.height + padding
+      && other.y < self.y + self.height + padding
+  }
+
+  pub fn contains(&self, (x, y): (usize, usize)) -> bool {
+    x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+  }
+}
+
+/// Extra info from a generator about good places to put things.
+#[derive(Debug, Clone, Default)]
+pub struct PlacementHints {
+  /// The middle of each room that was carved, if the generator makes rooms.
+  pub room_centers: Vec<(usize, usize)>,
+  /// Spots that must get a random item.
+  pub item_spots: Vec<(usize, usize)>,
+  /// Spots that must get a monster.
+  pub monster_spots: Vec<(usize, usize)>,
+  /// Stairs down that have already been placed.
+  pub stairs_down_spots: Vec<(usize, usize)>,
+  /// Spots that must get a key, so that locked doors can be opened.
+  pub key_spots: Vec<(usize, usize)>,
+  /// A room that can only be entered through a locked door.
+  pub locked_room: Option<Rect>,
+}
+
+/// The output of a generator, ready to be turned into a level.
+#[derive(Debug, Clone)]
+pub struct GeneratedMap {
+  pub terrain: VecImage<Terrain>,
+  pub hints: PlacementHints,
+  /// How many times the generator failed before this map came out of it.
+  pub failed_attempts: usize,
+}
+
+/// Why a level couldn't be generated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GenerationError {
+  /// The map is too small for the generator to fit anything into.
+  MapTooSmall { width: usize, height: usize },
+  /// The generator used up its whole budget without making a good map.
+  RetriesExhausted { attempts: usize },
+  /// There's no floor on the level to put anything on.
+  NoFloor { z: i32 },
+}
+
+impl ::std::fmt::Display for GenerationError {
+  fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+    match self {
+      GenerationError::MapTooSmall { width, height } => write!(f, "a {}x{} map is too small", width, height),
+      GenerationError::RetriesExhausted { attempts } => write!(f, "gave up after {} attempts", attempts),
+      GenerationError::NoFloor { z } => write!(f, "level {} has no floor", z),
+    }
+  }
+}
+
+impl ::std::error::Error for GenerationError {}
+
+/// How many times a generator gets to try before the fallback layout is used.
+pub const GENERATION_ATTEMPTS: usize = 3;
+
+/// How many times the cellular automaton starts over before giving up.
+pub const CAVE_ATTEMPTS: usize = 20;
+
+/// How likely each cell is to start out as a wall in the usual caves.
+pub const CAVE_FILL_PERCENT: u32 = 45;
+
+/// How many times the cave automaton runs in the usual caves.
+pub const CAVE_ITERATIONS: usize = 5;
+
+/// Anything that can lay out the terrain of a level.
+///
+/// Generators should always leave a wall around the outside edge of the map,
+/// and all of the floor they make should be connected. They must always finish
+/// in a bounded amount of time, giving back an error if they can't make a good
+/// map within their budget.
+pub trait MapGenerator {
+  fn generate(&self, width: usize, height: usize, gen: &mut PCG32) -> Result<GeneratedMap, GenerationError>;
+}
+
+/// Runs a generator a few times, and if it keeps failing uses the fallback
+/// layout instead. The map that comes back says how many tries failed.
+///
+/// This only draws from `gen`, so the same seed always makes the same map no
+/// matter how many tries it takes.
+pub fn generate_level_map(generator: &dyn MapGenerator, width: usize, height: usize, gen: &mut PCG32) -> Result<GeneratedMap, GenerationError> {
+  for attempt in 0..GENERATION_ATTEMPTS {
+    if let Ok(mut map) = generator.generate(width, height, gen) {
+      map.failed_attempts = attempt;
+      return Ok(map);
+    }
+  }
+  let mut map = fallback_map(width, height)?;
+  map.failed_attempts = GENERATION_ATTEMPTS;
+  Ok(map)
+}
+
+/// The layout used when nothing else works: one big room, the same every time.
+pub fn fallback_map(width: usize, height: usize) -> Result<GeneratedMap, GenerationError> {
+  if width < 3 || height < 3 {
+    return Err(GenerationError::MapTooSmall { width, height });
+  }
+  let mut terrain = VecImage::new(width, height);
+  let room = Rect {
+    x: 1,
+    y: 1,
+    width: width - 2,
+    height: height - 2,
+  };
+  carve_rect(&mut terrain, room);
+  Ok(GeneratedMap {
+    terrain,
+    hints: PlacementHints {
+      room_centers: vec![room.center()],
+      ..PlacementHints::default()
+    },
+    failed_attempts: 0,
+  })
+}
+
+/// Picks the generator that builds the level at the given depth.
+///
+/// The first few levels are always caves, after that the styles rotate every
+/// few levels. Themes with natural caves are caves all the way through, grown
+/// however the theme likes.
+pub fn generator_for_depth(depth: i32) -> Box<dyn MapGenerator> {
+  let theme = theme_for_depth(depth).definition();
+  let caves = CellularCaves {
+    fill_percent: theme.cave_fill_percent,
+    iterations: theme.cave_iterations,
+  };
+  if theme.natural_caves {
+    return Box::new(caves);
+  }
+  match (depth.abs() / 3) % 4 {
+    0 => Box::new(caves),
+    1 => Box::new(RoomsAndCorridors {
+      max_rooms: 14,
+      min_size: 4,
+      max_size: 9,
+    }),
+    2 => Box::new(DrunkardsWalk { floor_percent: 40 }),
+    _ => Box::new(BinarySpacePartition { min_leaf_size: 8 }),
+  }
+}
+
+/// Lays out the whole map for the level at the given depth, vault and all.
+///
+/// Everything random comes out of `gen`, so this can run on any thread and
+/// still make the same map.
+pub fn generate_map_for_depth(depth: i32, gen: &mut PCG32) -> Result<GeneratedMap, GenerationError> {
+  let generator = generator_for_depth(depth);
+  let (width, height) = level_dimensions_for_depth(depth);
+  let mut map = generate_level_map(&*generator, width, height, gen)?;
+  add_ice_patches(&mut map, theme_for_depth(depth).definition().ice_patches, gen);
+
+  // Sometimes stamp in a vault, keeping clear of the key and locked room
+  if gen.next_u32() >> 31 == 0 {
+    let vaults = builtin_vaults();
+    let vault = &vaults[roll_between(gen, 0, vaults.len() - 1)];
+    let avoid = protected_cells(&map.hints);
+    stamp_vault(&mut map, vault, &avoid, gen);
+  }
+  Ok(map)
+}
+
+/// The cells that a vault mustn't cover or tunnel through: wherever a key has
+/// to go, and the locked room along with its walls and door.
+pub fn protected_cells(hints: &PlacementHints) -> Vec<(usize, usize)> {
+  let mut out = hints.key_spots.clone();
+  if let Some(room) = hints.locked_room {
+    for y in (room.y - 1)..=(room.y + room.height) {
+      for x in (room.x - 1)..=(room.x + room.width) {
+        out.push((x, y));
+      }
+    }
+  }
+  out
+}
+
+/// Picks the width and height of the level at the given depth.
+///
+/// Most levels are square, but every so often there's a long narrow tunnel or
+/// a huge open cavern to break things up.
+pub fn level_dimensions_for_depth(depth: i32) -> (usize, usize) {
+  let depth = depth.abs();
+  if depth > 0 && depth % 7 == 0 {
+    (100, 80)
+  } else if depth > 0 && depth % 5 == 0 {
+    (120, 20)
+  } else {
+    (50, 50)
+  }
+}
+
+pub(crate) fn roll_between(gen: &mut PCG32, low: usize, high: usize) -> usize {
+  RandRangeInclusive32::new(low as u32..=high as u32).roll_with(gen) as usize
+}
+
+fn carve_rect(terrain: &mut VecImage<Terrain>, rect: Rect) {
+  for y in rect.y..(rect.y + rect.height) {
+    for x in rect.x..(rect.x + rect.width) {
+      terrain[(x, y)] = Terrain::Floor;
+    }
+  }
+}
+
+/// Digs an L-shaped corridor between two points.
+fn carve_corridor(terrain: &mut VecImage<Terrain>, (x1, y1): (usize, usize), (x2, y2): (usize, usize), gen: &mut PCG32) {
+  let corner = if gen.next_u32() >> 31 == 0 { (x2, y1) } else { (x1, y2) };
+  for &((ax, ay), (bx, by)) in [((x1, y1), corner), (corner, (x2, y2))].iter() {
+    for y in ay.min(by)..=ay.max(by) {
+      for x in ax.min(bx)..=ax.max(bx) {
+        terrain[(x, y)] = Terrain::Floor;
+      }
+    }
+  }
+}
+
+/// The classic cellular automaton caves.
+#[derive(Debug, Clone, Copy)]
+pub struct CellularCaves {
+  /// How likely each cell is to start out as a wall.
+  pub fill_percent: u32,
+  /// How many times the automaton runs over the map. More makes smoother caves.
+  pub iterations: usize,
+}
+
+impl Default for CellularCaves {
+  fn default() -> Self {
+    CellularCaves {
+      fill_percent: CAVE_FILL_PERCENT,
+      iterations: CAVE_ITERATIONS,
+    }
+  }
+}
+
+impl MapGenerator for CellularCaves {
+  fn generate(&self, width: usize, height: usize, gen: &mut PCG32) -> Result<GeneratedMap, GenerationError> {
+    if width < 3 || height < 3 {
+      return Err(GenerationError::MapTooSmall { width, height });
+    }
+    let caves = make_cellular_caves_with(width, height, self.fill_percent, self.iterations, gen)?;
+    let mut terrain = VecImage::new(width, height);
+    for (x, y, tile) in caves.iter() {
+      terrain[(x, y)] = if *tile { Terrain::Wall } else { Terrain::Floor };
+    }
+    Ok(GeneratedMap {
+      terrain,
+      hints: PlacementHints::default(),
+      failed_attempts: 0,
+    })
+  }
+}
+
+/// How many cells in the ring just outside of a room aren't wall, not counting
+/// the corners, since nothing gets in through those.
+fn openings_around(terrain: &VecImage<Terrain>, room: &Rect) -> usize {
+  let (left, right) = (room.x - 1, room.x + room.width);
+  let (bottom, top) = (room.y - 1, room.y + room.height);
+  let is_open = |spot: (usize, usize)| terrain[spot] != Terrain::Wall;
+  let across = (room.x..right).filter(|&x| is_open((x, bottom))).count() + (room.x..right).filter(|&x| is_open((x, top))).count();
+  let up = (room.y..top).filter(|&y| is_open((left, y))).count() + (room.y..top).filter(|&y| is_open((right, y))).count();
+  across + up
+}
+
+/// The spots just outside of a room where a corridor comes in through a gap
+/// that's exactly one cell wide.
+fn doorways_of(terrain: &VecImage<Terrain>, room: &Rect) -> Vec<(usize, usize)> {
+  let mut out = vec![];
+  let (left, right) = (room.x - 1, room.x + room.width);
+  let (bottom, top) = (room.y - 1, room.y + room.height);
+  let is_wall = |spot: (usize, usize)| terrain[spot] == Terrain::Wall;
+  for x in room.x..right {
+    for &y in [bottom, top].iter() {
+      if terrain[(x, y)] == Terrain::Floor && is_wall((x - 1, y)) && is_wall((x + 1, y)) {
+        out.push((x, y));
+      }
+    }
+  }
+  for y in room.y..top {
+    for &x in [left, right].iter() {
+      if terrain[(x, y)] == Terrain::Floor && is_wall((x, y - 1)) && is_wall((x, y + 1)) {
+        out.push((x, y));
+      }
+    }
+  }
+  out
+}
+
+/// Rectangular rooms, each one joined to the last by a corridor.
+///
+/// Most doorways get a door, and if the last room is a dead end its door is
+/// locked, with the key left in the first room.
+#[derive(Debug, Clone, Copy)]
+pub struct RoomsAndCorridors {
+  pub max_rooms: usize,
+  pub min_size: usize,
+  pub max_size: usize,
+}
+
+impl MapGenerator for RoomsAndCorridors {
+  fn generate(&self, width: usize, height: usize, gen: &mut PCG32) -> Result<GeneratedMap, GenerationError> {
+    if width < self.min_size + 3 || height < self.min_size + 3 {
+      return Err(GenerationError::MapTooSmall { width, height });
+    }
+    let mut terrain = VecImage::new(width, height);
+    let max_size = self.max_size.min(width - 3).min(height - 3).max(self.min_size);
+    let mut rooms: Vec<Rect> = vec![];
+    for _ in 0..self.max_rooms {
+      let room_width = roll_between(gen, self.min_size, max_size);
+      let room_height = roll_between(gen, self.min_size, max_size);
+      let room = Rect {
+        x: roll_between(gen, 1, width - room_width - 1),
+        y: roll_between(gen, 1, height - room_height - 1),
+        width: room_width,
+        height: room_height,
+      };
+      if rooms.iter().any(|other| room.near(other, 1)) {
+        continue;
+      }
+      carve_rect(&mut terrain, room);
+      if let Some(previous) = rooms.last() {
+        carve_corridor(&mut terrain, previous.center(), room.center(), gen);
+      }
+      rooms.push(room);
+    }
+    let mut hints = PlacementHints {
+      room_centers: rooms.iter().map(|room| room.center()).collect(),
+      ..PlacementHints::default()
+    };
+    let room_doorways: Vec<Vec<(usize, usize)>> = rooms.iter().map(|room| doorways_of(&terrain, room)).collect();
+    for &spot in room_doorways.iter().flat_map(|doorways| doorways.iter()) {
+      if !gen.next_u32().is_multiple_of(3) {
+        terrain[spot] = Terrain::DoorClosed;
+      }
+    }
+    if rooms.len() >= 3 && room_doorways[rooms.len() - 1].len() == 1 && openings_around(&terrain, &rooms[rooms.len() - 1]) == 1 {
+      terrain[room_doorways[rooms.len() - 1][0]] = Terrain::DoorLocked;
+      hints.key_spots.push(rooms[0].center());
+      hints.locked_room = rooms.last().cloned();
+    }
+    Ok(GeneratedMap {
+      terrain,
+      hints,
+      failed_attempts: 0,
+    })
+  }
+}
+
+/// Splits the map in half over and over, then puts a room in each piece.
+#[derive(Debug, Clone, Copy)]
+pub struct BinarySpacePartition {
+  pub min_leaf_size: usize,
+}
+
+impl BinarySpacePartition {
+  /// Fills in the area, giving back a room center from inside it.
+  fn split(&self, area: Rect, terrain: &mut VecImage<Terrain>, rooms: &mut Vec<Rect>, gen: &mut PCG32) -> (usize, usize) {
+    let min_leaf = self.min_leaf_size.max(5);
+    let can_split_x = area.width >= 2 * min_leaf;
+    let can_split_y = area.height >= 2 * min_leaf;
+    if !can_split_x && !can_split_y {
+      let room_width = roll_between(gen, 3.min(area.width - 2), area.width - 2);
+      let room_height = roll_between(gen, 3.min(area.height - 2), area.height - 2);
+      let room = Rect {
+        x: roll_between(gen, area.x + 1, area.x + area.width - 1 - room_width),
+        y: roll_between(gen, area.y + 1, area.y + area.height - 1 - room_height),
+        width: room_width,
+        height: room_height,
+      };
+      carve_rect(terrain, room);
+      rooms.push(room);
+      return room.center();
+    }
+    let split_x = if can_split_x && can_split_y {
+      gen.next_u32() >> 31 == 0
+    } else {
+      can_split_x
+    };
+    let (first, second) = if split_x {
+      let cut = roll_between(gen, min_leaf, area.width - min_leaf);
+      (
+        Rect { width: cut, ..area },
+        Rect {
+          x: area.x + cut,
+          width: area.width - cut,
+          ..area
+        },
+      )
+    } else {
+      let cut = roll_between(gen, min_leaf, area.height - min_leaf);
+      (
+        Rect { height: cut, ..area },
+        Rect {
+          y: area.y + cut,
+          height: area.height - cut,
+          ..area
+        },
+      )
+    };
+    let first_center = self.split(first, terrain, rooms, gen);
+    let second_center = self.split(second, terrain, rooms, gen);
+    carve_corridor(terrain, first_center, second_center, gen);
+    first_center
+  }
+}
+
+impl MapGenerator for BinarySpacePartition {
+  fn generate(&self, width: usize, height: usize, gen: &mut PCG32) -> Result<GeneratedMap, GenerationError> {
+    if width < 5 || height < 5 {
+      return Err(GenerationError::MapTooSmall { width, height });
+    }
+    let mut terrain = VecImage::new(width, height);
+    let mut rooms = vec![];
+    let whole_map = Rect {
+      x: 0,
+      y: 0,
+      width,
+      height,
+    };
+    self.split(whole_map, &mut terrain, &mut rooms, gen);
+    Ok(GeneratedMap {
+      terrain,
+      hints: PlacementHints {
+        room_centers: rooms.iter().map(|room| room.center()).collect(),
+        ..PlacementHints::default()
+      },
+      failed_attempts: 0,
+    })
+  }
+}
+
+/// A single digger stumbles around at random until enough floor is open.
+///
+/// The digger gets a fixed number of steps per cell it needs to open up, so a
+/// really unlucky walk gives up instead of wandering forever.
+#[derive(Debug, Clone, Copy)]
+pub struct DrunkardsWalk {
+  pub floor_percent: usize,
+}
+
+/// How many steps the drunkard gets for each floor cell it has to dig.
+const DRUNKARD_STEPS_PER_CELL: usize = 100;
+
+impl MapGenerator for DrunkardsWalk {
+  fn generate(&self, width: usize, height: usize, gen: &mut PCG32) -> Result<GeneratedMap, GenerationError> {
+    if width < 3 || height < 3 {
+      return Err(GenerationError::MapTooSmall { width, height });
+    }
+    let mut terrain = VecImage::new(width, height);
+    let interior_cells = (width - 2) * (height - 2);
+    let target = interior_cells * self.floor_percent.min(100) / 100;
+    let step_budget = (target + 1) * DRUNKARD_STEPS_PER_CELL;
+    let (mut x, mut y) = (width / 2, height / 2);
+    terrain[(x, y)] = Terrain::Floor;
+    let mut floor_count = 1;
+    let mut steps = 0;
+    while floor_count < target {
+      steps += 1;
+      if steps > step_budget {
+        return Err(GenerationError::RetriesExhausted { attempts: step_budget });
+      }
+      match gen.next_u32() >> 30 {
+        0 => x = (x + 1).min(width - 2),
+        1 => x = (x - 1).max(1),
+        2 => y = (y + 1).min(height - 2),
+        _ => y = (y - 1).max(1),
+      }
+      if terrain[(x, y)] != Terrain::Floor {
+        terrain[(x, y)] = Terrain::Floor;
+        floor_count += 1;
+      }
+    }
+    Ok(GeneratedMap {
+      terrain,
+      hints: PlacementHints::default(),
+      failed_attempts: 0,
+    })
+  }
+}
+
+/// A summed-area table of the walls in a cave buffer, so that the walls in any
+/// box can be counted with four lookups. Cells past the right and top edges
+/// count as wall, just like they do in the reference automaton.
+struct WallTable {
+  stride: usize,
+  sums: Vec<u32>,
+}
+
+impl WallTable {
+  /// How far past the right and top edges the table reaches.
+  const PADDING: usize = 2;
+
+  fn new(buf: &VecImage<bool>) -> Self {
+    let (width, height) = (buf.width(), buf.height());
+    let stride = width + Self::PADDING + 1;
+    let rows = height + Self::PADDING + 1;
+    let mut sums = vec![0u32; stride * rows];
+    for y in 1..rows {
+      let mut row_total = 0;
+      for x in 1..stride {
+        let (buf_x, buf_y) = (x - 1, y - 1);
+        if buf_x >= width || buf_y >= height || buf[(buf_x, buf_y)] {
+          row_total += 1;
+        }
+        sums[y * stride + x] = sums[(y - 1) * stride + x] + row_total;
+      }
+    }
+    WallTable { stride, sums }
+  }
+
+  /// The walls in the box from `(x0, y0)` to `(x1, y1)`, inclusive.
+  fn walls_in(&self, (x0, y0): (usize, usize), (x1, y1): (usize, usize)) -> u32 {
+    let sum_at = |x: usize, y: usize| self.sums[y * self.stride + x];
+    sum_at(x1 + 1, y1 + 1) + sum_at(x0, y0) - sum_at(x0, y1 + 1) - sum_at(x1 + 1, y0)
+  }
+
+  /// Counts walls the same way that the reference automaton does.
+  ///
+  /// That means a window that would hang off the bottom or left edge counts
+  /// nothing at all, the center cell counts, and the cell at the origin never
+  /// does.
+  fn range_count(&self, src: &VecImage<bool>, x: usize, y: usize, range: usize) -> u32 {
+    debug_assert!(range > 0 && range <= Self::PADDING);
+    if x < range || y < range {
+      return 0;
+    }
+    let total = self.walls_in((x - range, y - range), (x + range, y + range));
+    if x == range && y == range && src[(0, 0)] {
+      total - 1
+    } else {
+      total
+    }
+  }
+}
+
+/// Grows cellular automaton caves, keeping only the biggest connected cave if
+/// it takes up at least half of the map.
+///
+/// The output is exactly the same as the original cave automaton for the same
+/// generator state, it's just a lot faster.
+pub fn make_cellular_caves(width: usize, height: usize, gen: &mut PCG32) -> Result<VecImage<bool>, GenerationError> {
+  make_cellular_caves_with(width, height, CAVE_FILL_PERCENT, CAVE_ITERATIONS, gen)
+}
+
+/// Grows caves like `make_cellular_caves`, but with the starting wall chance
+/// and the number of automaton passes picked by the caller.
+pub fn make_cellular_caves_with(
+  width: usize,
+  height: usize,
+  fill_percent: u32,
+  iterations: usize,
+  gen: &mut PCG32,
+) -> Result<VecImage<bool>, GenerationError> {
+  let cave_copy = |src: &VecImage<bool>, dest: &mut VecImage<bool>| {
+    let table = WallTable::new(src);
+    for (x, y, mut_ref) in dest.iter_mut() {
+      *mut_ref = table.range_count(src, x, y, 1) >= 5 || table.range_count(src, x, y, 2) <= 1;
+    }
+  };
+  let flood_copy = |src: &VecImage<bool>, dest: &mut VecImage<bool>, gen: &mut PCG32| {
+    dest.set_all(true);
+    let mut copied_count = 0;
+    let start = {
+      let d_width = RandRangeInclusive32::new(0..=((width - 1) as u32));
+      let d_height = RandRangeInclusive32::new(0..=((height - 1) as u32));
+      let mut x = d_width.roll_with(gen) as usize;
+      let mut y = d_height.roll_with(gen) as usize;
+      let mut tries = 0;
+      while src[(x, y)] {
+        x = d_width.roll_with(gen) as usize;
+        y = d_height.roll_with(gen) as usize;
+        tries += 1;
+        if tries > 100 {
+          return 0;
+        }
+      }
+      (x, y)
+    };
+    let mut visited = vec![false; width * height];
+    let mut open_list = vec![start];
+    while let Some(loc) = open_list.pop() {
+      let index = loc.1 * width + loc.0;
+      if visited[index] {
+        continue;
+      }
+      visited[index] = true;
+      if !src[loc] {
+        dest[loc] = false;
+        copied_count += 1;
+        if loc.0 > 1 {
+          open_list.push((loc.0 - 1, loc.1));
+        }
+        if loc.0 < (width - 2) {
+          open_list.push((loc.0 + 1, loc.1));
+        }
+        if loc.1 > 1 {
+          open_list.push((loc.0, loc.1 - 1));
+        }
+        if loc.1 < (height - 2) {
+          open_list.push((loc.0, loc.1 + 1));
+        }
+      }
+    }
+    copied_count
+  };
+
+  let d100 = RandRangeInclusive32::new(1..=100);
+  let mut buffer_a: VecImage<bool> = VecImage::new(width, height);
+  let mut buffer_b: VecImage<bool> = VecImage::new(width, height);
+
+  for _ in 0..CAVE_ATTEMPTS {
+    // fill the initial buffer, all cells equally likely.
+    for (_x, _y, mut_ref) in buffer_a.iter_mut() {
+      *mut_ref = d100.roll_with(gen) <= fill_percent;
+    }
+    // cave copy back and forth, swapping so the latest is always in A
+    for _ in 0..iterations {
+      cave_copy(&buffer_a, &mut buffer_b);
+      std::mem::swap(&mut buffer_a, &mut buffer_b);
+    }
+    // good stuff is in A, flood copy over into B
+    let copied_count = flood_copy(&buffer_a, &mut buffer_b, gen);
+    if copied_count >= (width * height) / 2 {
+      return Ok(buffer_b);
+    }
+  }
+  Err(GenerationError::RetriesExhausted { attempts: CAVE_ATTEMPTS })
+}
+
+#[cfg(test)]
+#[path = "../benches/common/cave_reference.rs"]
+mod cave_reference;
+
+#[cfg(test)]
+fn assert_sealed_and_connected(map: &GeneratedMap) {
+  let terrain = &map.terrain;
+  let (width, height) = (terrain.width(), terrain.height());
+  let mut floors = vec![];
+  for (x, y, tile) in terrain.iter() {
+    if tile.is_passable() {
+      assert!(x > 0 && y > 0 && x < width - 1 && y < height - 1, "floor on the edge at {:?}", (x, y));
+      floors.push((x, y));
+    }
+  }
+  assert!(!floors.is_empty());
+  let analysis = LevelAnalysis::new(&Level::from_image(terrain), &[]);
+  assert_eq!(analysis.region_count(), 1, "not all floor is connected");
+  assert_eq!(analysis.region_size(0), floors.len());
+}
+
+#[test]
+fn generators_make_sealed_connected_maps() {
+  for seed in 1..=10 {
+    let gen = &mut PCG32::new(seed);
+    for depth in 0..12 {
+      let (width, height) = level_dimensions_for_depth(-depth);
+      let map = generate_level_map(&*generator_for_depth(-depth), width, height, gen).unwrap();
+      assert_sealed_and_connected(&map);
+    }
+  }
+}
+
+#[test]
+fn generators_give_up_on_hopeless_maps() {
+  let generators: Vec<Box<dyn MapGenerator>> = vec![
+    Box::new(CellularCaves::default()),
+    Box::new(RoomsAndCorridors {
+      max_rooms: 14,
+      min_size: 4,
+      max_size: 9,
+    }),
+    Box::new(DrunkardsWalk { floor_percent: 100 }),
+    Box::new(BinarySpacePartition { min_leaf_size: 8 }),
+  ];
+  for seed in [0, 1, 2, 0xFFFF_FFFF, 0xDEAD_BEEF, ::std::u64::MAX].iter() {
+    let gen = &mut PCG32::new(*seed);
+    for generator in generators.iter() {
+      for &(width, height) in [(0, 0), (1, 1), (2, 9), (3, 3), (4, 4), (6, 6), (7, 50), (50, 7)].iter() {
+        // these are allowed to fail, but they have to finish.
+        if let Ok(map) = generator.generate(width, height, gen) {
+          assert_sealed_and_connected(&map);
+        }
+        match generate_level_map(&**generator, width, height, gen) {
+          Ok(map) => assert_sealed_and_connected(&map),
+          Err(e) => assert_eq!(e, GenerationError::MapTooSmall { width, height }),
+        }
+      }
+    }
+  }
+  // caves this small can never be half open, so they always run out of tries.
+  assert_eq!(
+    CellularCaves::default().generate(4, 4, &mut PCG32::new(5)).err(),
+    Some(GenerationError::RetriesExhausted { attempts: CAVE_ATTEMPTS })
+  );
+  let fallback = generate_level_map(&CellularCaves::default(), 4, 4, &mut PCG32::new(5)).unwrap();
+  assert_eq!(fallback.failed_attempts, GENERATION_ATTEMPTS);
+}
+
+#[test]
+fn fallback_map_is_always_the_same() {
+  let first = fallback_map(20, 10).unwrap();
+  let second = fallback_map(20, 10).unwrap();
+  assert_sealed_and_connected(&first);
+  assert!(first.terrain.iter().zip(second.terrain.iter()).all(|(a, b)| a == b));
+  assert!(fallback_map(2, 10).is_err());
+}
+
+#[test]
+fn picking_floor_always_finishes() {
+  for seed in [0, 1, 0xFFFF_FFFF, ::std::u64::MAX].iter() {
+    let mut game = GameWorld::try_new(*seed).unwrap();
+    let z = game.player_location.z;
+    let mut lonely = Level::new(30, 30);
+    lonely.set(17, 4, Terrain::Floor);
+    game.levels.insert(z, lonely);
+    assert_eq!(game.pick_random_floor(z), Ok(Location { x: 17, y: 4, z }));
+    game.levels.insert(z, Level::new(30, 30));
+    assert_eq!(game.pick_random_floor(z), Err(GenerationError::NoFloor { z }));
+    assert_eq!(game.pick_random_floor(z - 50), Err(GenerationError::NoFloor { z: z - 50 }));
+  }
+}
+
+#[test]
+fn fast_caves_match_the_reference() {
+  for &(width, height) in [(50, 50), (100, 80), (120, 20), (3, 3), (9, 5)].iter() {
+    for seed in 0..6 {
+      let fast = make_cellular_caves(width, height, &mut PCG32::new(seed));
+      let reference = cave_reference::cellular_caves_reference(width, height, &mut PCG32::new(seed));
+      match (fast, reference) {
+        (Ok(fast), Ok(reference)) => {
+          assert!(fast.iter().zip(reference.iter()).all(|(a, b)| a == b), "{}x{} seed {}", width, height, seed);
+        }
+        (fast, reference) => assert_eq!(fast.err(), reference.err(), "{}x{} seed {}", width, height, seed),
+      }
+    }
+  }
+}
@@ -0,0 +1,274 @@
+//! Moves levels the player isn't using out to disk, and back again.
+//!
+//! Each paged out level is written to its own file in the page directory,
+//! which is normally the same directory as the save file. The file names have
+//! the world's seed in them, so that two different games never share pages.
+//! Page files are only scratch space though: saving a game reads every paged
+//! out level back in and puts it right into the save, so a save always loads
+//! with the levels that it was made with, whatever happened to the page files
+//! after that. A page file is deleted as soon as its level is back in memory,
+//! and whatever pages are left get thrown out when the game is over.
+
+use super::*;
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+/// How many levels stay in memory when paging is turned on.
+pub const DEFAULT_LEVEL_CACHE_LIMIT: usize = 3;
+
+/// Everything that lives on a single level, bundled up for writing to disk.
+#[derive(Debug, Serialize, Deserialize)]
+struct PagedLevel {
+  z: i32,
+  level: Level,
+  items: Vec<(Location, Vec<Item>)>,
+  traps: Vec<(Location, Trap)>,
+  creatures: Vec<(Location, Creature)>,
+}
+
+impl GameWorld {
+  /// The file that a paged out level is stored in, if paging is turned on.
+  pub fn level_page_path(&self, z: i32) -> Option<PathBuf> {
+    self.level_page_dir.as_ref().map(|dir| dir.join(format!("kasidin.{}.level{}.page", self.seed, z)))
+  }
+
+  /// Writes a level and everything on it out to disk, then forgets about it.
+  ///
+  /// The player's own level can't be paged out.
+  pub fn page_out_level(&mut self, z: i32) -> io::Result<()> {
+    let path = self.level_page_path(z).ok_or_else(|| io::Error::other("Level paging is not turned on!"))?;
+    if z == self.player_location.z {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, "Can't page out the player's level!"));
+    }
+    let level = match self.levels.remove(&z) {
+      Some(level) => level,
+      None => return Ok(()),
+    };
+    let item_spots: Vec<Location> = self.item_locations.keys().filter(|loc_ref| loc_ref.z == z).cloned().collect();
+    let items = item_spots
+      .into_iter()
+      .map(|loc| (loc, self.item_locations.remove(&loc).unwrap()))
+      .collect();
+    let trap_spots: Vec<Location> = self.trap_locations.keys().filter(|loc_ref| loc_ref.z == z).cloned().collect();
+    let traps = trap_spots
+      .into_iter()
+      .map(|loc| (loc, self.trap_locations.remove(&loc).unwrap()))
+      .collect();
+    let creature_spots: Vec<Location> = self.creature_locations.keys().filter(|loc_ref| loc_ref.z == z).cloned().collect();
+    let mut creatures = vec![];
+    for loc in creature_spots {
+      let cid = self.creature_locations.remove(&loc).unwrap();
+      let index = self
+        .creature_list
+        .iter()
+        .position(|creature_ref| creature_ref.id == cid)
+        .expect("Our locations and list are out of sync!");
+      creatures.push((loc, self.creature_list.remove(index)));
+    }
+    let paged = PagedLevel {
+      z,
+      level,
+      items,
+      traps,
+      creatures,
+    };
+    let encoded: Vec<u8> =
+      bincode::serialize(&paged).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Couldn't serialize the level!"))?;
+    match File::create(&path).and_then(|mut f| f.write_all(&encoded)) {
+      Ok(()) => {
+        self.paged_out_levels.insert(z);
+        self.recent_levels.retain(|&recent_z| recent_z != z);
+        Ok(())
+      }
+      Err(e) => {
+        // put everything back so that nothing is lost.
+        self.restore_paged_level(paged);
+        Err(e)
+      }
+    }
+  }
+
+  /// Loads a paged out level back into memory, and deletes its page file.
+  pub fn page_in_level(&mut self, z: i32) -> io::Result<()> {
+    if !self.paged_out_levels.contains(&z) {
+      return Ok(());
+    }
+    let paged = self.read_paged_level(z)?;
+    self.restore_paged_level(paged);
+    self.paged_out_levels.remove(&z);
+    self.remove_page_file(z);
+    Ok(())
+  }
+
+  /// Deletes the page files of every paged out level, and forgets those levels.
+  /// For when the game is over, or about to be replaced by another one.
+  pub fn discard_level_pages(&mut self) {
+    let paged_out: Vec<i32> = self.paged_out_levels.iter().cloned().collect();
+    for z in paged_out {
+      self.remove_page_file(z);
+    }
+    self.paged_out_levels.clear();
+  }
+
+  /// A page file that can't be deleted is just left behind, since the level
+  /// is never read from it again anyway.
+  fn remove_page_file(&self, z: i32) {
+    if let Some(path) = self.level_page_path(z) {
+      fs::remove_file(path).ok();
+    }
+  }
+
+  fn read_paged_level(&self, z: i32) -> io::Result<PagedLevel> {
+    let path = self.level_page_path(z).ok_or_else(|| io::Error::other("Level paging is not turned on!"))?;
+    let mut file_bytes: Vec<u8> = vec![];
+    File::open(&path)?.read_to_end(&mut file_bytes)?;
+    bincode::deserialize(&file_bytes).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Couldn't deserialize the level!"))
+  }
+
+  /// Bundles the world up for a save file, with every paged out level read
+  /// back in and put in with it.
+  pub fn to_save_bytes(&self) -> io::Result<Vec<u8>> {
+    let mut paged_levels = vec![];
+    for &z in self.paged_out_levels.iter() {
+      paged_levels.push(self.read_paged_level(z)?);
+    }
+    bincode::serialize(&(self, paged_levels)).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Couldn't serialize the game!"))
+  }
+
+  /// Unpacks a save made by `to_save_bytes`. Every level starts out back in
+  /// memory, and gets paged out again as the player moves around. Any page
+  /// files left over from when the save was made are deleted.
+  pub fn from_save_bytes(bytes: &[u8]) -> io::Result<Self> {
+    let (mut world, paged_levels): (GameWorld, Vec<PagedLevel>) =
+      bincode::deserialize(bytes).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Couldn't deserialize the game!"))?;
+    for paged in paged_levels {
+      world.restore_paged_level(paged);
+    }
+    world.discard_level_pages();
+    Ok(world)
+  }
+
+  fn restore_paged_level(&mut self, paged: PagedLevel) {
+    let PagedLevel {
+      z,
+      level,
+      items,
+      traps,
+      creatures,
+    } = paged;
+    self.levels.insert(z, level);
+    for (loc, item_vec) in items {
+      self.item_locations.insert(loc, item_vec);
+    }
+    for (loc, trap) in traps {
+      self.trap_locations.insert(loc, trap);
+    }
+    for (loc, creature) in creatures {
+      self.creature_locations.insert(loc, CreatureID(creature.id.0));
+      self.creature_list.push(creature);
+    }
+  }
+
+  /// Marks a level as recently used, loading it if it was paged out and paging
+  /// out the least recently used levels if there are too many in memory.
+  pub fn touch_level(&mut self, z: i32) -> io::Result<()> {
+    self.page_in_level(z)?;
+    self.recent_levels.retain(|&recent_z| recent_z != z);
+    self.recent_levels.push(z);
+    if self.level_page_dir.is_none() {
+      return Ok(());
+    }
+    while self.levels.len() > self.level_cache_limit.max(1) {
+      let player_z = self.player_location.z;
+      let oldest = self
+        .levels
+        .keys()
+        .cloned()
+        .filter(|&level_z| level_z != z && level_z != player_z)
+        .min_by_key(|level_z| self.recent_levels.iter().position(|recent_z| recent_z == level_z));
+      match oldest {
+        Some(oldest_z) => self.page_out_level(oldest_z)?,
+        None => break,
+      }
+    }
+    Ok(())
+  }
+}
+
+/// A page directory for a test, which gets cleaned up even if the test fails.
+#[cfg(test)]
+struct ScratchPageDir(PathBuf);
+
+#[cfg(test)]
+impl ScratchPageDir {
+  fn new(test_name: &str) -> Self {
+    let dir = ::std::env::temp_dir().join(format!("kasidin-{}-{}", test_name, ::std::process::id()));
+    ::std::fs::create_dir_all(&dir).unwrap();
+    ScratchPageDir(dir)
+  }
+}
+
+#[cfg(test)]
+impl Drop for ScratchPageDir {
+  fn drop(&mut self) {
+    ::std::fs::remove_dir_all(&self.0).ok();
+  }
+}
+
+#[test]
+fn page_out_and_back_in() {
+  let page_dir = ScratchPageDir::new("paging-test");
+  let mut game = GameWorld::new(12345);
+  game.level_page_dir = Some(page_dir.0.clone());
+  let top_z = game.player_location.z;
+  game.add_z_layer().unwrap();
+  let lower_z = game.deepest_depth;
+  let creature_count = game.creature_list.len();
+  let item_count: usize = game.item_locations.values().map(|v| v.len()).sum();
+  let lower_terrain: Vec<Terrain> = (0..game.levels[&lower_z].width as i32).map(|x| game.levels[&lower_z].terrain(x, 5)).collect();
+
+  game.page_out_level(lower_z).unwrap();
+  assert!(!game.levels.contains_key(&lower_z));
+  assert!(game.creature_locations.keys().all(|loc| loc.z != lower_z));
+  assert!(game.creature_list.len() < creature_count);
+  assert!(game.page_out_level(top_z).is_err(), "the player's level must stay loaded");
+
+  let page_path = game.level_page_path(lower_z).unwrap();
+  assert!(page_path.exists());
+  game.page_in_level(lower_z).unwrap();
+  assert!(!page_path.exists(), "the page file was left behind");
+  assert_eq!(game.creature_list.len(), creature_count);
+  assert_eq!(game.item_locations.values().map(|v| v.len()).sum::<usize>(), item_count);
+  let reloaded_terrain: Vec<Terrain> = (0..game.levels[&lower_z].width as i32).map(|x| game.levels[&lower_z].terrain(x, 5)).collect();
+  assert_eq!(lower_terrain, reloaded_terrain);
+  for (loc, cid) in game.creature_locations.iter() {
+    assert!(game.creature_list.iter().any(|creature_ref| &creature_ref.id == cid), "{:?} lost its creature", loc);
+  }
+}
+
+#[test]
+fn saves_carry_their_paged_out_levels() {
+  let page_dir = ScratchPageDir::new("paged-save-test");
+  let mut game = GameWorld::new(54321);
+  game.level_page_dir = Some(page_dir.0.clone());
+  game.add_z_layer().unwrap();
+  let lower_z = game.deepest_depth;
+  let creature_count = game.creature_list.len();
+  game.page_out_level(lower_z).unwrap();
+  let save = game.to_save_bytes().unwrap();
+
+  // keep playing after the save, and make a mess of the page files.
+  game.page_in_level(lower_z).unwrap();
+  game.creature_list.retain(|creature_ref| creature_ref.is_the_player);
+  game.creature_locations.retain(|&loc, _| loc.z != lower_z);
+  game.page_out_level(lower_z).unwrap();
+
+  let loaded = GameWorld::from_save_bytes(&save).unwrap();
+  assert!(loaded.levels.contains_key(&lower_z));
+  assert!(loaded.paged_out_levels.is_empty());
+  assert!(!loaded.level_page_path(lower_z).unwrap().exists(), "the page file was left behind");
+  assert_eq!(loaded.creature_list.len(), creature_count);
+  assert_eq!(loaded.creature_locations.len(), creature_count);
+}
@@ -0,0 +1,259 @@
+//! Picking random spots on a level for things to go, following whatever rules
+//! each kind of thing has.
+//!
+//! Everything that gets scattered over a level goes through here: monsters,
+//! items, stairs, traps, and anything that gets moved somewhere random later
+//! on. The rules are listed most important first. If no floor at all follows
+//! every rule, the last rule is dropped and the search goes again, until only
+//! the rules that the placement requires are left.
+
+use super::*;
+
+/// Monsters start out at least this many steps from any way into a level.
+pub const ARRIVAL_SAFE_DISTANCE: i32 = 6;
+
+/// Staircases up and down are kept at least this many steps apart.
+pub const STAIRS_APART_DISTANCE: i32 = 5;
+
+/// How many random rolls are tried before checking every floor tile instead.
+const PLACEMENT_TRIES: usize = 200;
+
+/// A rule about where something is allowed to go.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlacementRule {
+  /// At least this many steps from the spot, ignoring walls.
+  AwayFrom(Location, i32),
+  /// Somewhere that can't be seen from the spot.
+  OutOfSightOf(Location),
+  /// Nothing else is there yet: no creature, no items, and no trap.
+  Unclaimed,
+  /// No creature there, or within this many steps of it.
+  NoCreatureWithin(i32),
+  /// Inside the given region of the level's analysis.
+  InRegion(usize),
+  /// At least this many steps from the level's staircases up, walking.
+  FarFromEntrances(u32),
+  /// Outside of the given rectangle.
+  OutsideRect(Rect),
+}
+
+/// A set of rules for one level, with the expensive parts worked out up front
+/// so that it can be used for lots of spots.
+///
+/// The layout and sight lines are as of when it was made, but whatever's on
+/// the level is always checked as it is right now.
+#[derive(Debug, Clone)]
+pub struct Placement {
+  pub z: i32,
+  pub rules: Vec<PlacementRule>,
+  /// How many rules at the front of the list can never be dropped.
+  pub required: usize,
+  analysis: LevelAnalysis,
+  sight: HashMap<Location, HashSet<Location>>,
+}
+
+impl Placement {
+  /// Makes the first `count` rules ones that never get dropped.
+  pub fn requiring(mut self, count: usize) -> Self {
+    self.required = count.min(self.rules.len());
+    self
+  }
+
+  /// If the first `rule_count` rules all allow the spot.
+  pub fn allows_with(&self, game: &GameWorld, loc: Location, rule_count: usize) -> bool {
+    loc.z == self.z
+      && self.rules.iter().take(rule_count).all(|rule| match *rule {
+        PlacementRule::AwayFrom(spot, distance) => spot.z != loc.z || spot.manhattan_distance(loc) >= distance,
+        PlacementRule::OutOfSightOf(spot) => !self.sight.get(&spot).map(|seen| seen.contains(&loc)).unwrap_or(false),
+        PlacementRule::Unclaimed => {
+          !game.creature_locations.contains_key(&loc)
+            && game.item_locations.get(&loc).map(|items| items.is_empty()).unwrap_or(true)
+            && !game.trap_locations.contains_key(&loc)
+        }
+        PlacementRule::NoCreatureWithin(distance) => !game.creature_near(loc, distance),
+        PlacementRule::InRegion(region) => self.analysis.region_at(loc.x, loc.y) == Some(region),
+        PlacementRule::FarFromEntrances(distance) => self.analysis.distance_at(loc.x, loc.y).map(|d| d >= distance).unwrap_or(true),
+        PlacementRule::OutsideRect(rect) => loc.x < 0 || loc.y < 0 || !rect.contains((loc.x as usize, loc.y as usize)),
+      })
+  }
+
+  /// If every rule allows the spot.
+  pub fn allows(&self, game: &GameWorld, loc: Location) -> bool {
+    self.allows_with(game, loc, self.rules.len())
+  }
+}
+
+impl GameWorld {
+  /// Every spot that can be seen from `from` within the radius.
+  pub fn seen_from(&self, from: Location, radius: i32) -> HashSet<Location> {
+    let mut seen = HashSet::new();
+    if let Some(level_ref) = self.levels.get(&from.z) {
+      let z = from.z;
+      ppfov(
+        (from.x, from.y),
+        radius,
+        |x, y| !level_ref.terrain(x, y).is_transparent(),
+        |x, y| {
+          seen.insert(Location { x, y, z });
+        },
+      );
+    }
+    seen
+  }
+
+  /// If there's a creature at the spot or within `distance` steps of it.
+  pub fn creature_near(&self, loc: Location, distance: i32) -> bool {
+    for y in (loc.y - distance)..=(loc.y + distance) {
+      for x in (loc.x - distance)..=(loc.x + distance) {
+        let near = Location { x, y, z: loc.z };
+        if near.manhattan_distance(loc) <= distance && self.creature_locations.contains_key(&near) {
+          return true;
+        }
+      }
+    }
+    false
+  }
+
+  /// Works out everything the rules need for placing things on a level.
+  pub fn placement(&self, z: i32, rules: &[PlacementRule]) -> Result<Placement, GenerationError> {
+    let analysis = self.analyze_level(z).ok_or(GenerationError::NoFloor { z })?;
+    let level_ref = &self.levels[&z];
+    let sight_radius = level_ref.width.max(level_ref.height) as i32;
+    let mut sight = HashMap::new();
+    for rule in rules {
+      if let PlacementRule::OutOfSightOf(spot) = *rule {
+        let seen = self.seen_from(spot, sight_radius);
+        sight.insert(spot, seen);
+      }
+    }
+    Ok(Placement {
+      z,
+      rules: rules.to_vec(),
+      required: 0,
+      analysis,
+      sight,
+    })
+  }
+
+  /// Picks a random floor tile that the placement allows, dropping rules off
+  /// of the end until something fits. It's only an error if even the required
+  /// rules can't be kept.
+  pub fn pick_floor_for(&mut self, placement: &Placement) -> Result<Location, GenerationError> {
+    let z = placement.z;
+    for _tries in 0..PLACEMENT_TRIES {
+      let spot = self.pick_random_floor(z)?;
+      if placement.allows(self, spot) {
+        return Ok(spot);
+      }
+    }
+    // The rolls all missed, so look at every floor tile instead.
+    let level_ref = self.levels.get(&z).ok_or(GenerationError::NoFloor { z })?;
+    let mut floor = vec![];
+    for y in 0..level_ref.height as i32 {
+      for x in 0..level_ref.width as i32 {
+        if level_ref.terrain(x, y) == Terrain::Floor {
+          floor.push(Location { x, y, z });
+        }
+      }
+    }
+    for rule_count in (placement.required..=placement.rules.len()).rev() {
+      let allowed: Vec<Location> = floor.iter().cloned().filter(|spot| placement.allows_with(self, *spot, rule_count)).collect();
+      if !allowed.is_empty() {
+        return Ok(allowed[roll_between(&mut self.gen, 0, allowed.len() - 1)]);
+      }
+    }
+    Err(GenerationError::NoFloor { z })
+  }
+
+  /// Picks a random floor tile following the rules given, for when there's
+  /// only one thing to place. The first `required` rules are never dropped.
+  pub fn pick_floor_with(&mut self, z: i32, rules: &[PlacementRule], required: usize) -> Result<Location, GenerationError> {
+    let placement = self.placement(z, rules)?.requiring(required);
+    self.pick_floor_for(&placement)
+  }
+
+  /// Rules that keep newly placed monsters away from where the player would
+  /// come into the level. Only the first one, that the spot is open, is a
+  /// must.
+  pub(crate) fn monster_placement_rules(&self, z: i32) -> Vec<PlacementRule> {
+    let mut rules = vec![PlacementRule::NoCreatureWithin(0)];
+    let entrances: Vec<Location> = self
+      .levels
+      .get(&z)
+      .map(|level_ref| {
+        level_ref
+          .stairs
+          .iter()
+          .filter(|stairs_ref| level_ref.terrain(stairs_ref.x, stairs_ref.y) == Terrain::StairsUp)
+          .map(|stairs_ref| Location {
+            x: stairs_ref.x,
+            y: stairs_ref.y,
+            z,
+          })
+          .collect()
+      })
+      .unwrap_or(vec![]);
+    rules.extend(entrances.iter().map(|&entrance| PlacementRule::AwayFrom(entrance, ARRIVAL_SAFE_DISTANCE)));
+    rules.extend(entrances.iter().map(|&entrance| PlacementRule::OutOfSightOf(entrance)));
+    rules
+  }
+}
+
+#[test]
+fn monsters_keep_away_from_the_stairs_up() {
+  for seed in 1..6 {
+    let mut game = GameWorld::new(seed);
+    for _ in 0..4 {
+      game.add_z_layer().unwrap();
+    }
+    // the player starts out right on the way out, but nothing else should.
+    for (loc, _) in game.creature_locations.iter().filter(|&(loc, _)| *loc != game.player_location) {
+      let level_ref = &game.levels[&loc.z];
+      for stairs_ref in level_ref.stairs.iter() {
+        let stairs_loc = Location {
+          x: stairs_ref.x,
+          y: stairs_ref.y,
+          z: loc.z,
+        };
+        if level_ref.terrain(stairs_ref.x, stairs_ref.y) == Terrain::StairsUp {
+          assert!(loc.manhattan_distance(stairs_loc) >= ARRIVAL_SAFE_DISTANCE, "seed {} {:?} by {:?}", seed, loc, stairs_loc);
+        }
+      }
+    }
+  }
+}
+
+#[test]
+fn stairs_down_stay_apart_from_stairs_up() {
+  for seed in 1..6 {
+    let mut game = GameWorld::new(seed);
+    for _ in 0..6 {
+      game.add_z_layer().unwrap();
+    }
+    for level_ref in game.levels.values() {
+      let terrain_of = |stairs_ref: &&Staircase| level_ref.terrain(stairs_ref.x, stairs_ref.y);
+      for up in level_ref.stairs.iter().filter(|s| terrain_of(s) == Terrain::StairsUp) {
+        for down in level_ref.stairs.iter().filter(|s| terrain_of(s) == Terrain::StairsDown) {
+          let apart = (up.x - down.x).abs() + (up.y - down.y).abs();
+          assert!(apart >= STAIRS_APART_DISTANCE, "seed {}: {:?} and {:?}", seed, up, down);
+        }
+      }
+    }
+  }
+}
+
+#[test]
+fn placement_drops_rules_that_cannot_be_kept() {
+  let mut game = GameWorld::new(2468);
+  let z = game.player_location.z;
+  let player = game.player_location;
+  // nothing is a million steps from the player, so that rule has to go.
+  let rules = [PlacementRule::NoCreatureWithin(0), PlacementRule::AwayFrom(player, 1_000_000)];
+  for _ in 0..20 {
+    let spot = game.pick_floor_with(z, &rules, 1).unwrap();
+    assert_eq!(game.terrain_at(spot), Terrain::Floor);
+    assert!(!game.creature_locations.contains_key(&spot));
+  }
+  // but if it's required then it's an error instead.
+  assert_eq!(game.pick_floor_with(z, &rules, 2), Err(GenerationError::NoFloor { z }));
+}
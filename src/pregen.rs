@@ -0,0 +1,81 @@
+//! Makes the next level's map on a worker thread before anyone needs it.
+//!
+//! Each map gets its own generator, forked off of the main one right after the
+//! level above it is finished. The worker only ever touches that fork, so the
+//! map comes out the same whether the worker finished ages ago, is still going
+//! when the player takes the stairs, or never ran at all because the game was
+//! loaded from a save in between.
+
+use super::*;
+
+use std::thread::{self, JoinHandle};
+
+/// A map that a worker thread is making for the level at depth `z`.
+#[derive(Debug)]
+pub struct PregeneratedMap {
+  z: i32,
+  handle: JoinHandle<Result<GeneratedMap, GenerationError>>,
+}
+
+impl GameWorld {
+  /// Forks off the generator for the level at depth `z` and starts making its
+  /// map in the background.
+  pub(crate) fn start_pregenerating(&mut self, z: i32) {
+    self.next_map_gen = self.gen.fork();
+    let mut map_gen = self.next_map_gen.clone();
+    let spawned = thread::Builder::new()
+      .name(format!("pregen level {}", z))
+      .spawn(move || generate_map_for_depth(z, &mut map_gen));
+    self.pregenerating = match spawned {
+      Ok(handle) => Some(PregeneratedMap { z, handle }),
+      // no worker, so it'll just get made when it's needed.
+      Err(_) => None,
+    };
+  }
+
+  /// The map for the level at depth `z`, waiting on the worker if it has one
+  /// and making the map right here if it doesn't.
+  pub(crate) fn take_level_map(&mut self, z: i32) -> Result<GeneratedMap, GenerationError> {
+    let finished = match self.pregenerating.take() {
+      Some(pregen) if pregen.z == z => pregen.handle.join().ok(),
+      _ => None,
+    };
+    match finished {
+      Some(result) => result,
+      None => generate_map_for_depth(z, &mut self.next_map_gen.clone()),
+    }
+  }
+
+  /// If the next level's map is already being made in the background.
+  pub fn is_pregenerating(&self) -> bool {
+    self.pregenerating.is_some()
+  }
+}
+
+#[test]
+fn pregenerated_levels_match_ones_made_on_the_spot() {
+  for seed in 1..4 {
+    let mut waited = GameWorld::new(seed);
+    let mut rushed = GameWorld::new(seed);
+    assert!(waited.is_pregenerating());
+    for _ in 0..3 {
+      // one world lets the worker finish, the other pretends that it was just
+      // loaded from a save and never had a worker at all.
+      thread::sleep(::std::time::Duration::from_millis(20));
+      rushed.pregenerating = None;
+      waited.add_z_layer().unwrap();
+      rushed.add_z_layer().unwrap();
+      let z = waited.deepest_depth;
+      assert_eq!(z, rushed.deepest_depth);
+      let (waited_level, rushed_level) = (&waited.levels[&z], &rushed.levels[&z]);
+      assert_eq!((waited_level.width, waited_level.height), (rushed_level.width, rushed_level.height));
+      for y in 0..waited_level.height as i32 {
+        for x in 0..waited_level.width as i32 {
+          assert_eq!(waited_level.terrain(x, y), rushed_level.terrain(x, y), "seed {} at {:?}", seed, (x, y, z));
+        }
+      }
+      assert_eq!(waited_level.stairs, rushed_level.stairs);
+      assert_eq!(waited.creature_list.len(), rushed.creature_list.len());
+    }
+  }
+}
@@ -70,6 +70,16 @@ impl PCG32 {
     x ^= x >> 18;
     ((x >> 27) as u32).rotate_right(rotation)
   }
+
+  /// Makes a new generator, seeded from this one, with a stream of its own.
+  ///
+  /// The fork can be handed off somewhere else and used at any time without
+  /// changing what this generator rolls next.
+  pub fn fork(&mut self) -> Self {
+    let high = self.next_u32() as u64;
+    let low = self.next_u32() as u64;
+    Self::new(high << 32 | low)
+  }
 }
 
 #[derive(Debug, Clone)]
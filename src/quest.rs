@@ -0,0 +1,119 @@
+//! What the player is down here for.
+//!
+//! Somewhere deep in the dungeon is the artifact, and the top level has the
+//! way back out to the surface. The game is won by carrying the artifact up
+//! the stairs on the top level. Trying to leave without it just gets a
+//! reminder that there's still work to do.
+
+use super::*;
+
+/// What the artifact is called.
+pub const ARTIFACT_NAME: &str = "Heart of the Mountain";
+
+/// The level that the artifact is on in a normal game, down at the bottom of
+/// the magma depths.
+pub const ARTIFACT_DEPTH: i32 = -19;
+
+/// The level that the game starts on, with the way out of the dungeon.
+pub const TOP_LEVEL_Z: i32 = 0;
+
+impl GameWorld {
+  /// Puts the artifact on a freshly made level, as far from the staircases up
+  /// as it can be.
+  pub(crate) fn place_artifact(&mut self, z: i32) -> Result<(), GenerationError> {
+    let max_distance = self.analyze_level(z).and_then(|analysis| analysis.max_distance()).unwrap_or(0);
+    let rules = [PlacementRule::Unclaimed, PlacementRule::FarFromEntrances(max_distance * 3 / 4)];
+    let spot = self.pick_floor_with(z, &rules, 1)?;
+    self.item_locations.entry(spot).or_default().push(Item::Artifact);
+    Ok(())
+  }
+
+  /// Where the way out of the dungeon is, if the top level is in memory.
+  pub fn exit_location(&self) -> Option<Location> {
+    self.levels.get(&TOP_LEVEL_Z).and_then(|level_ref| {
+      level_ref
+        .stairs
+        .iter()
+        .find(|stairs_ref| stairs_ref.destination.is_none() && level_ref.terrain(stairs_ref.x, stairs_ref.y) == Terrain::StairsUp)
+        .map(|stairs_ref| Location {
+          x: stairs_ref.x,
+          y: stairs_ref.y,
+          z: TOP_LEVEL_Z,
+        })
+    })
+  }
+
+  /// If the player is carrying the artifact.
+  pub fn player_has_artifact(&self) -> bool {
+    self
+      .creature_list
+      .iter()
+      .find(|creature_ref| creature_ref.is_the_player)
+      .map(|player_ref| player_ref.inventory.contains(&Item::Artifact))
+      .unwrap_or(false)
+  }
+
+  /// The player takes the way out, which wins the game if they have the
+  /// artifact with them.
+  pub(crate) fn try_to_escape(&mut self) {
+    if self.outcome.is_some() {
+      return;
+    }
+    if self.player_has_artifact() {
+      self.outcome = Some(GameOutcome::Won);
+      self.push_message(format!("You climb out into the daylight with the {}!", ARTIFACT_NAME));
+    } else {
+      self.push_message(format!("You can't leave without the {}.", ARTIFACT_NAME));
+    }
+  }
+}
+
+#[test]
+fn the_artifact_waits_at_the_bottom() {
+  let mut game = GameWorld::new(4242);
+  game.artifact_depth = -3;
+  for _ in 0..4 {
+    game.add_z_layer().unwrap();
+  }
+  let artifact_spots: Vec<Location> = game
+    .item_locations
+    .iter()
+    .filter(|&(_, items)| items.contains(&Item::Artifact))
+    .map(|(&loc, _)| loc)
+    .collect();
+  assert_eq!(artifact_spots.len(), 1);
+  assert_eq!(artifact_spots[0].z, -3);
+  assert_eq!(game.terrain_at(artifact_spots[0]), Terrain::Floor);
+}
+
+#[test]
+fn escaping_with_the_artifact() {
+  let mut game = GameWorld::new(4242);
+  let exit = game.exit_location().unwrap();
+  assert_eq!(game.player_location, exit);
+  game.change_floor(1);
+  assert_eq!(game.outcome, None);
+  assert_eq!(game.player_location, exit);
+
+  let player_mut = game.creature_list.iter_mut().find(|creature_ref| creature_ref.is_the_player).unwrap();
+  player_mut.inventory.push(Item::Artifact);
+  game.change_floor(1);
+  assert_eq!(game.outcome, Some(GameOutcome::Won));
+}
+
+#[test]
+fn ice_bombs_leave_the_artifact_and_keys_alone() {
+  let mut game = GameWorld::new(4242);
+  let target_delta = [(1, 0), (-1, 0), (0, 1), (0, -1)]
+    .iter()
+    .map(|&(x, y)| Location { x, y, z: 0 })
+    .find(|&delta| game.terrain_at(game.player_location + delta) == Terrain::Floor)
+    .unwrap();
+  let target = game.player_location + target_delta;
+  game.item_locations.insert(target, vec![Item::PotionHealth, Item::Artifact, Item::Key]);
+  let player_mut = game.creature_list.iter_mut().find(|creature_ref| creature_ref.is_the_player).unwrap();
+  player_mut.inventory = vec![Item::BombIce];
+  game.use_targeted_item('a', target_delta);
+  assert_eq!(game.terrain_at(target), Terrain::Ice);
+  assert_eq!(game.item_locations[&target], vec![Item::Artifact, Item::Key]);
+}
@@ -0,0 +1,270 @@
+//! Works out the shape of a level: which parts connect to which, where the
+//! chokepoints and dead ends are, and how far everything is from the way in.
+//!
+//! Everything is worked out on the cardinal grid, the same way creatures move.
+//! A cell counts as open if a creature could get through it, doors included,
+//! as long as it wouldn't fall through the floor doing so.
+
+use super::*;
+
+use std::collections::VecDeque;
+
+/// If a cell is part of the walkable layout of a level.
+fn is_open(terrain: Terrain) -> bool {
+  terrain.is_passable() && !terrain.properties().falls_through
+}
+
+/// The layout of one level, as of when it was analyzed.
+#[derive(Debug, Clone)]
+pub struct LevelAnalysis {
+  width: usize,
+  height: usize,
+  region: Vec<Option<usize>>,
+  region_sizes: Vec<usize>,
+  distance: Vec<Option<u32>>,
+  chokepoint: Vec<bool>,
+  dead_end: Vec<bool>,
+}
+
+impl LevelAnalysis {
+  /// Analyzes a level, measuring distances from the entrances given. With no
+  /// entrances there aren't any distances at all.
+  pub fn new(level: &Level, entrances: &[(i32, i32)]) -> Self {
+    let (width, height) = (level.width, level.height);
+    let open: Vec<bool> = (0..width * height).map(|index| is_open(level.terrain((index % width) as i32, (index / width) as i32))).collect();
+    let neighbors = |index: usize| {
+      let (x, y) = (index % width, index / width);
+      let mut out = [None; 4];
+      if x + 1 < width {
+        out[0] = Some(index + 1);
+      }
+      if x > 0 {
+        out[1] = Some(index - 1);
+      }
+      if y + 1 < height {
+        out[2] = Some(index + width);
+      }
+      if y > 0 {
+        out[3] = Some(index - width);
+      }
+      out
+    };
+    let open_neighbor_count = |index: usize| neighbors(index).iter().filter(|n| n.map(|n| open[n]).unwrap_or(false)).count();
+
+    // Regions, chokepoints, and dead ends all come out of one depth first
+    // search. It keeps its own stack so that big caves can't overflow ours.
+    let mut region = vec![None; width * height];
+    let mut region_sizes = vec![];
+    let mut chokepoint = vec![false; width * height];
+    let mut dead_end = vec![false; width * height];
+    let mut discovered: Vec<usize> = vec![0; width * height];
+    let mut low: Vec<usize> = vec![0; width * height];
+    let mut parent: Vec<Option<usize>> = vec![None; width * height];
+    let mut clock = 1;
+    for root in 0..width * height {
+      if !open[root] || region[root].is_some() {
+        continue;
+      }
+      let region_id = region_sizes.len();
+      region_sizes.push(0);
+      let mut root_children = 0;
+      let mut stack = vec![(root, 0)];
+      region[root] = Some(region_id);
+      discovered[root] = clock;
+      low[root] = clock;
+      clock += 1;
+      while let Some(&mut (here, ref mut next_neighbor)) = stack.last_mut() {
+        if *next_neighbor < 4 {
+          let there = neighbors(here)[*next_neighbor];
+          *next_neighbor += 1;
+          let there = match there {
+            Some(there) if open[there] => there,
+            _ => continue,
+          };
+          if discovered[there] == 0 {
+            region[there] = Some(region_id);
+            discovered[there] = clock;
+            low[there] = clock;
+            clock += 1;
+            parent[there] = Some(here);
+            if here == root {
+              root_children += 1;
+            }
+            stack.push((there, 0));
+          } else if parent[here] != Some(there) {
+            low[here] = low[here].min(discovered[there]);
+          }
+        } else {
+          stack.pop();
+          region_sizes[region_id] += 1;
+          dead_end[here] = open_neighbor_count(here) == 1;
+          if let Some(up) = parent[here] {
+            low[up] = low[up].min(low[here]);
+            if up != root && low[here] >= discovered[up] {
+              chokepoint[up] = true;
+            }
+          }
+        }
+      }
+      chokepoint[root] = root_children > 1;
+    }
+
+    // Then the distances, breadth first from all of the entrances at once.
+    let mut distance = vec![None; width * height];
+    let mut queue = VecDeque::new();
+    for &(x, y) in entrances {
+      if let Some(index) = level.get(x, y).map(|_| y as usize * width + x as usize) {
+        if open[index] && distance[index].is_none() {
+          distance[index] = Some(0);
+          queue.push_back(index);
+        }
+      }
+    }
+    while let Some(here) = queue.pop_front() {
+      let next_distance = distance[here].map(|d| d + 1);
+      for there in neighbors(here).iter().filter_map(|&n| n) {
+        if open[there] && distance[there].is_none() {
+          distance[there] = next_distance;
+          queue.push_back(there);
+        }
+      }
+    }
+
+    LevelAnalysis {
+      width,
+      height,
+      region,
+      region_sizes,
+      distance,
+      chokepoint,
+      dead_end,
+    }
+  }
+
+  fn index_of(&self, x: i32, y: i32) -> Option<usize> {
+    if x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height {
+      Some(y as usize * self.width + x as usize)
+    } else {
+      None
+    }
+  }
+
+  fn positions_where<'a>(&'a self, cells: &'a [bool]) -> impl Iterator<Item = (i32, i32)> + 'a {
+    let width = self.width;
+    cells
+      .iter()
+      .enumerate()
+      .filter(|&(_, &flag)| flag)
+      .map(move |(index, _)| ((index % width) as i32, (index / width) as i32))
+  }
+
+  /// How many separate connected regions the level has.
+  pub fn region_count(&self) -> usize {
+    self.region_sizes.len()
+  }
+
+  /// How many cells are in the given region.
+  pub fn region_size(&self, region: usize) -> usize {
+    self.region_sizes.get(region).cloned().unwrap_or(0)
+  }
+
+  /// The region that a cell is in, `None` for walls and the like.
+  pub fn region_at(&self, x: i32, y: i32) -> Option<usize> {
+    self.index_of(x, y).and_then(|index| self.region[index])
+  }
+
+  /// Steps from the nearest entrance, `None` if none of them can reach it.
+  pub fn distance_at(&self, x: i32, y: i32) -> Option<u32> {
+    self.index_of(x, y).and_then(|index| self.distance[index])
+  }
+
+  /// The farthest that anything is from the nearest entrance.
+  pub fn max_distance(&self) -> Option<u32> {
+    self.distance.iter().filter_map(|&d| d).max()
+  }
+
+  /// If walling off this cell would split its region in two.
+  pub fn is_chokepoint(&self, x: i32, y: i32) -> bool {
+    self.index_of(x, y).map(|index| self.chokepoint[index]).unwrap_or(false)
+  }
+
+  /// If this cell only has one way in or out.
+  pub fn is_dead_end(&self, x: i32, y: i32) -> bool {
+    self.index_of(x, y).map(|index| self.dead_end[index]).unwrap_or(false)
+  }
+
+  /// Every chokepoint, in row order.
+  pub fn chokepoints<'a>(&'a self) -> impl Iterator<Item = (i32, i32)> + 'a {
+    self.positions_where(&self.chokepoint)
+  }
+
+  /// Every dead end, in row order.
+  pub fn dead_ends<'a>(&'a self) -> impl Iterator<Item = (i32, i32)> + 'a {
+    self.positions_where(&self.dead_end)
+  }
+}
+
+impl GameWorld {
+  /// Analyzes a level in memory, measuring distances from its staircases up.
+  pub fn analyze_level(&self, z: i32) -> Option<LevelAnalysis> {
+    self.levels.get(&z).map(|level_ref| {
+      let entrances: Vec<(i32, i32)> = level_ref
+        .stairs
+        .iter()
+        .filter(|stairs_ref| level_ref.terrain(stairs_ref.x, stairs_ref.y) == Terrain::StairsUp)
+        .map(|stairs_ref| (stairs_ref.x, stairs_ref.y))
+        .collect();
+      LevelAnalysis::new(level_ref, &entrances)
+    })
+  }
+}
+
+/// Makes a level out of rows of text, `#` for wall and anything else floor,
+/// with the first row at the top.
+#[cfg(test)]
+fn level_from_rows(rows: &[&str]) -> Level {
+  let mut level = Level::new(rows[0].len(), rows.len());
+  for (row_index, row) in rows.iter().enumerate() {
+    let y = (rows.len() - 1 - row_index) as i32;
+    for (x, ch) in row.chars().enumerate() {
+      if ch != '#' {
+        level.set(x as i32, y, Terrain::Floor);
+      }
+    }
+  }
+  level
+}
+
+#[test]
+fn analyzing_two_rooms_and_a_corridor() {
+  let level = level_from_rows(&[
+    "##########",
+    "#...#....#",
+    "#...#.##.#",
+    "#........#",
+    "####.#####",
+    "####.###.#",
+    "##########",
+  ]);
+  let analysis = LevelAnalysis::new(&level, &[(1, 5)]);
+  // the lone cell in the bottom right corner is its own region.
+  assert_eq!(analysis.region_count(), 2);
+  assert_eq!(analysis.region_at(8, 1), Some(1));
+  assert_eq!(analysis.region_size(1), 1);
+  assert_eq!(analysis.region_at(0, 0), None);
+  assert_eq!(analysis.region_at(1, 5), analysis.region_at(4, 1));
+
+  // the door between the rooms and the corridor heading south are the only
+  // ways through, but the loop on the right has none.
+  assert!(analysis.is_chokepoint(4, 3));
+  assert!(analysis.is_chokepoint(4, 2));
+  assert!(!analysis.is_chokepoint(4, 1));
+  assert!(!analysis.is_chokepoint(7, 3));
+  assert!(!analysis.is_chokepoint(2, 4));
+  assert_eq!(analysis.dead_ends().collect::<Vec<_>>(), vec![(4, 1)]);
+
+  assert_eq!(analysis.distance_at(1, 5), Some(0));
+  assert_eq!(analysis.distance_at(4, 1), Some(7));
+  assert_eq!(analysis.distance_at(8, 1), None);
+  assert_eq!(analysis.max_distance(), Some(11));
+}
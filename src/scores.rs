@@ -0,0 +1,163 @@
+//! Keeping score, and remembering the best runs from one game to the next.
+//!
+//! A run's score comes from how deep the player got, how many monsters they
+//! killed, and how long they lasted, with a big bonus for actually escaping
+//! with the artifact. The best runs are kept in a little file of their own,
+//! next to the save file.
+
+use super::*;
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// How many runs the high score table keeps.
+pub const HIGH_SCORE_LIMIT: usize = 10;
+
+/// Points for every level below the top that the player reached.
+pub const SCORE_PER_DEPTH: u64 = 100;
+
+/// Points for every monster killed.
+pub const SCORE_PER_KILL: u64 = 10;
+
+/// Surviving this many turns is worth one point.
+pub const TURNS_PER_POINT: u64 = 20;
+
+/// Points for escaping with the artifact.
+pub const VICTORY_BONUS: u64 = 5000;
+
+/// The score of a run with the given results.
+pub fn score_for(depth: u32, kills: u32, turns: u64, won: bool) -> u64 {
+  let bonus = if won { VICTORY_BONUS } else { 0 };
+  depth as u64 * SCORE_PER_DEPTH + kills as u64 * SCORE_PER_KILL + turns / TURNS_PER_POINT + bonus
+}
+
+/// Seconds since the unix epoch, for dating runs.
+pub fn unix_time_now() -> u64 {
+  use std::time::{SystemTime, UNIX_EPOCH};
+  SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0)
+}
+
+/// One finished run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunRecord {
+  pub score: u64,
+  pub seed: u64,
+  /// When the run ended, in seconds since the unix epoch.
+  pub unix_time: u64,
+  /// How many levels below the top the player got.
+  pub depth: u32,
+  pub kills: u32,
+  pub turns: u64,
+  pub outcome: GameOutcome,
+}
+
+impl RunRecord {
+  /// The day that the run ended, as `YYYY-MM-DD`.
+  pub fn date(&self) -> String {
+    // Days to a civil date, from Howard Hinnant's date algorithms.
+    let days = (self.unix_time / 86_400) as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+  }
+}
+
+/// The best runs so far, best first.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HighScores {
+  pub runs: Vec<RunRecord>,
+}
+
+impl HighScores {
+  /// Reads the table from disk. A table that was never saved is just empty.
+  pub fn load(path: &Path) -> io::Result<Self> {
+    let mut f = match File::open(path) {
+      Ok(f) => f,
+      Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+      Err(e) => return Err(e),
+    };
+    let mut file_bytes: Vec<u8> = vec![];
+    f.read_to_end(&mut file_bytes)?;
+    bincode::deserialize(&file_bytes).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Couldn't deserialize the high scores!"))
+  }
+
+  /// Writes the table out to disk.
+  pub fn save(&self, path: &Path) -> io::Result<()> {
+    let encoded: Vec<u8> =
+      bincode::serialize(self).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Couldn't serialize the high scores!"))?;
+    File::create(path)?.write_all(&encoded)
+  }
+
+  /// Adds a run to the table, dropping whatever falls off the bottom. Gives
+  /// back where the run ended up, counting from 0, if it made the cut.
+  pub fn record(&mut self, run: RunRecord) -> Option<usize> {
+    // ties go to whoever got there first.
+    let rank = self.runs.iter().position(|old_run| old_run.score < run.score).unwrap_or(self.runs.len());
+    self.runs.insert(rank, run);
+    self.runs.truncate(HIGH_SCORE_LIMIT);
+    Some(rank).filter(|&rank| rank < HIGH_SCORE_LIMIT)
+  }
+}
+
+impl GameWorld {
+  /// The record of this run, once it's over.
+  pub fn run_record(&self, unix_time: u64) -> Option<RunRecord> {
+    self.outcome.map(|outcome| {
+      let depth = (TOP_LEVEL_Z - self.deepest_reached).max(0) as u32;
+      RunRecord {
+        score: score_for(depth, self.kills, self.turn_count, outcome == GameOutcome::Won),
+        seed: self.seed,
+        unix_time,
+        depth,
+        kills: self.kills,
+        turns: self.turn_count,
+        outcome,
+      }
+    })
+  }
+}
+
+#[test]
+fn escaping_beats_everything_else() {
+  let won = score_for(19, 30, 20_000, true);
+  let deep_but_dead = score_for(25, 80, 40_000, false);
+  assert!(won > deep_but_dead);
+  assert!(score_for(5, 0, 100, false) > score_for(4, 0, 100, false));
+  assert!(score_for(4, 1, 100, false) > score_for(4, 0, 100, false));
+}
+
+#[test]
+fn only_the_best_runs_are_kept() {
+  let run = |score: u64| RunRecord {
+    score,
+    seed: score,
+    unix_time: 1_539_907_200,
+    depth: 1,
+    kills: 0,
+    turns: 0,
+    outcome: GameOutcome::Died(DeathCause::Starvation),
+  };
+  let mut table = HighScores::default();
+  for score in 1..=HIGH_SCORE_LIMIT as u64 {
+    assert!(table.record(run(score * 10)).is_some());
+  }
+  assert_eq!(table.record(run(1)), None);
+  assert_eq!(table.record(run(55)), Some(5));
+  assert_eq!(table.runs.len(), HIGH_SCORE_LIMIT);
+  assert_eq!(table.runs[0].score, HIGH_SCORE_LIMIT as u64 * 10);
+  assert!(table.runs.windows(2).all(|pair| pair[0].score >= pair[1].score));
+  assert_eq!(table.runs[0].date(), "2018-10-19");
+
+  let path = ::std::env::temp_dir().join(format!("kasidin-scores-test-{}", ::std::process::id()));
+  table.save(&path).unwrap();
+  assert_eq!(HighScores::load(&path).unwrap(), table);
+  ::std::fs::remove_file(&path).ok();
+  assert_eq!(HighScores::load(&path).unwrap(), HighScores::default());
+}
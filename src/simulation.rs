@@ -0,0 +1,130 @@
+//! What happens on the levels that the player isn't on.
+//!
+//! Only the creatures on the player's own level take turns. Every other level
+//! sits still until the player comes back, and then the off level policy says
+//! what to do about the time that went by. Either nothing happened at all, or
+//! the monsters there get a few cheap random steps each to make up for it,
+//! which is nowhere near a real turn but means a level never looks exactly
+//! the way it was left.
+
+use super::*;
+
+/// The most random steps a monster gets when its level catches up.
+pub const CATCH_UP_STEPS: u64 = 30;
+
+/// What happens to levels while the player is somewhere else.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OffLevelPolicy {
+  /// Nothing moves until the player comes back.
+  Freeze,
+  /// When the player comes back the monsters wander around a bit, more the
+  /// longer the player was away, and forget anything they were chasing.
+  #[default]
+  CatchUp,
+}
+
+impl GameWorld {
+  /// Brings a level up to the current turn, now that the player is back on it.
+  pub(crate) fn catch_up_level(&mut self, z: i32) {
+    let elapsed = match self.levels.get_mut(&z) {
+      Some(level_mut) => {
+        let elapsed = self.turn_count.saturating_sub(level_mut.last_turn_here);
+        level_mut.last_turn_here = self.turn_count;
+        elapsed
+      }
+      None => return,
+    };
+    if self.off_level_policy == OffLevelPolicy::Freeze || elapsed == 0 {
+      return;
+    }
+    // The hash map's order changes from run to run, but the random rolls need
+    // to go out in the same order every time.
+    let mut monsters: Vec<(usize, Location)> = self
+      .creature_locations
+      .iter()
+      .filter(|&(loc, _)| loc.z == z && *loc != self.player_location)
+      .map(|(&loc, cid)| (cid.0, loc))
+      .collect();
+    monsters.sort_by_key(|&(cid, _)| cid);
+    let steps = elapsed.min(CATCH_UP_STEPS);
+    for (cid, start) in monsters {
+      let mut here = start;
+      for _ in 0..steps {
+        let target = here + match self.gen.next_u32() >> 30 {
+          0 => Location { x: 0, y: 1, z: 0 },
+          1 => Location { x: 0, y: -1, z: 0 },
+          2 => Location { x: 1, y: 0, z: 0 },
+          3 => Location { x: -1, y: 0, z: 0 },
+          impossible => unreachable!("u32 >> 30: {}", impossible),
+        };
+        let properties = self.terrain_at(target).properties();
+        if properties.walkable && !properties.falls_through && !self.monster_avoids(target) && !self.creature_locations.contains_key(&target) {
+          let moved = self.creature_locations.remove(&here).unwrap();
+          self.creature_locations.insert(target, moved);
+          here = target;
+        }
+      }
+      if let Some(creature_mut) = self.creature_list.iter_mut().find(|creature_ref| creature_ref.id.0 == cid) {
+        creature_mut.alerted_to = None;
+      }
+    }
+  }
+}
+
+/// Every creature on a level other than the player, by ID.
+#[cfg(test)]
+fn monsters_on(game: &GameWorld, z: i32) -> Vec<(usize, Location)> {
+  let mut monsters: Vec<(usize, Location)> = game
+    .creature_locations
+    .iter()
+    .filter(|&(loc, _)| loc.z == z && *loc != game.player_location)
+    .map(|(&loc, cid)| (cid.0, loc))
+    .collect();
+  monsters.sort_by_key(|&(cid, _)| cid);
+  monsters
+}
+
+/// Moves the player down to a brand new level, and gives back the top one.
+#[cfg(test)]
+fn leave_the_top_level(game: &mut GameWorld) -> i32 {
+  let top_z = game.player_location.z;
+  game.add_z_layer().unwrap();
+  let landing = game.pick_open_floor(top_z - 1).unwrap();
+  game.relocate_player(landing);
+  top_z
+}
+
+#[test]
+fn other_levels_sit_still() {
+  let mut game = GameWorld::new(97531);
+  let top_z = leave_the_top_level(&mut game);
+  let before = monsters_on(&game, top_z);
+  assert!(!before.is_empty());
+  for _ in 0..20 {
+    if game.outcome.is_some() {
+      break;
+    }
+    game.run_world_turn();
+  }
+  assert_eq!(before, monsters_on(&game, top_z));
+}
+
+#[test]
+fn coming_back_to_a_level() {
+  for &policy in [OffLevelPolicy::Freeze, OffLevelPolicy::CatchUp].iter() {
+    let mut game = GameWorld::new(97531);
+    game.off_level_policy = policy;
+    let top_z = leave_the_top_level(&mut game);
+    let before = monsters_on(&game, top_z);
+    game.turn_count += 500;
+    let back = game.pick_open_floor(top_z).unwrap();
+    game.relocate_player(back);
+    let after = monsters_on(&game, top_z);
+    assert_eq!(before.len(), after.len());
+    match policy {
+      OffLevelPolicy::Freeze => assert_eq!(before, after),
+      OffLevelPolicy::CatchUp => assert_ne!(before, after),
+    }
+    assert_eq!(game.levels[&top_z].last_turn_here, game.turn_count);
+  }
+}
@@ -0,0 +1,393 @@
+//! Which monsters live at which depths, how many of them, and in what company.
+//!
+//! The dungeon is split into bands of a few levels each, and every band has a
+//! chart of the groups that show up there. Most groups are a lone monster, but
+//! some kinds come in packs that all start out close together. Once in a while
+//! a group comes up from the band below instead, tougher than anything else on
+//! the level.
+//!
+//! A level also gets new monsters wandering in over time, somewhere the player
+//! can't see. The deeper the level and the longer the player has spent on it,
+//! the sooner they show up, so there's no camping on a level that's been
+//! cleared out. They do stop coming once the level is crowded enough.
+
+use super::*;
+
+/// How many levels deep each band of the spawn tables is.
+pub const DEPTH_BAND_SIZE: i32 = 4;
+
+/// Monsters for every 10,000 cells of map, unless the game says otherwise.
+pub const DEFAULT_MONSTER_DENSITY: usize = 100;
+
+/// One group in this many comes up from the next band down.
+pub const OUT_OF_DEPTH_ODDS: u32 = 25;
+
+/// How many steps away from its leader the rest of a pack can start out.
+pub const PACK_SPREAD: i32 = 3;
+
+/// The odds against a wandering monster each turn, on a fresh level at the top.
+pub const WANDERING_BASE_ODDS: u32 = 400;
+
+/// The odds against a wandering monster never get any better than this.
+pub const WANDERING_MIN_ODDS: u32 = 50;
+
+/// Wandering monsters try to show up at least this many steps from the player.
+pub const WANDERING_DISTANCE: i32 = 10;
+
+/// Wanderers stop showing up once a level has this many monsters for every
+/// 10,000 cells of map.
+pub const WANDERING_MONSTER_LIMIT: usize = 150;
+
+/// The odds against a wandering monster showing up on a turn, after `turns`
+/// turns spent on a level at the given depth.
+pub fn wandering_odds(depth: i32, turns: u32) -> u32 {
+  WANDERING_BASE_ODDS
+    .saturating_sub(depth.unsigned_abs() * 10)
+    .saturating_sub(turns / 10)
+    .max(WANDERING_MIN_ODDS)
+}
+
+/// A kind of monster, and how many of them show up together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpawnGroup {
+  pub kind: MonsterKind,
+  pub min_count: usize,
+  pub max_count: usize,
+}
+
+impl SpawnGroup {
+  pub fn single(kind: MonsterKind) -> Self {
+    Self::pack(kind, 1, 1)
+  }
+
+  pub fn pack(kind: MonsterKind, min_count: usize, max_count: usize) -> Self {
+    SpawnGroup { kind, min_count, max_count }
+  }
+}
+
+/// The band that a depth falls into, starting from 0 at the top.
+pub fn depth_band(depth: i32) -> i32 {
+  depth.abs() / DEPTH_BAND_SIZE
+}
+
+/// The groups that show up in the given band, and how often.
+pub fn monster_chart_for_band(band: i32) -> FrequencyChart<SpawnGroup> {
+  use MonsterKind::*;
+  match band {
+    0 => {
+      let mut chart = FrequencyChart::new(SpawnGroup::single(Kestrel), 40);
+      chart.push_item(SpawnGroup::pack(Kestrel, 2, 3), 4);
+      chart.push_item(SpawnGroup::single(MagmaSpitter), 6);
+      chart
+    }
+    1 => {
+      let mut chart = FrequencyChart::new(SpawnGroup::single(Kestrel), 30);
+      chart.push_item(SpawnGroup::pack(Kestrel, 2, 4), 8);
+      chart.push_item(SpawnGroup::single(MagmaSpitter), 12);
+      chart.push_item(SpawnGroup::single(RockThrower), 4);
+      chart
+    }
+    2 => {
+      let mut chart = FrequencyChart::new(SpawnGroup::single(Kestrel), 20);
+      chart.push_item(SpawnGroup::pack(Kestrel, 3, 5), 10);
+      chart.push_item(SpawnGroup::single(MagmaSpitter), 15);
+      chart.push_item(SpawnGroup::pack(MagmaSpitter, 2, 3), 4);
+      chart.push_item(SpawnGroup::single(RockThrower), 12);
+      chart.push_item(SpawnGroup::single(AshShaman), 3);
+      chart
+    }
+    _ => {
+      let mut chart = FrequencyChart::new(SpawnGroup::single(Kestrel), 10);
+      chart.push_item(SpawnGroup::pack(Kestrel, 4, 6), 10);
+      chart.push_item(SpawnGroup::single(MagmaSpitter), 15);
+      chart.push_item(SpawnGroup::pack(MagmaSpitter, 2, 4), 6);
+      chart.push_item(SpawnGroup::single(RockThrower), 15);
+      chart.push_item(SpawnGroup::pack(RockThrower, 2, 3), 4);
+      chart.push_item(SpawnGroup::single(AshShaman), 10 + band as u32);
+      chart
+    }
+  }
+}
+
+/// The groups that show up at the given depth: the band's usual groups, plus
+/// whatever the theme there adds.
+pub fn monster_chart_for_depth(depth: i32) -> FrequencyChart<SpawnGroup> {
+  let mut chart = monster_chart_for_band(depth_band(depth));
+  for &(group, weight) in theme_for_depth(depth).definition().extra_monsters.iter() {
+    chart.push_item(group, weight);
+  }
+  chart
+}
+
+/// What got put on a level when it was populated, for debugging.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpawnStats {
+  /// How many of each kind made it onto the level.
+  pub placed: BTreeMap<MonsterKind, usize>,
+  /// How many groups were rolled, whether or not they all fit.
+  pub groups: usize,
+  /// How many monsters came up from the band below.
+  pub out_of_depth: usize,
+  /// How many monsters didn't fit anywhere and were left out.
+  pub crowded_out: usize,
+  /// How many of the monsters placed wandered in after the level was made.
+  pub wanderers: usize,
+}
+
+impl SpawnStats {
+  /// How many monsters were placed in all.
+  pub fn total(&self) -> usize {
+    self.placed.values().sum()
+  }
+}
+
+impl ::std::fmt::Display for SpawnStats {
+  fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+    write!(
+      f,
+      "{} monsters in {} groups ({} out of depth, {} crowded out, {} wanderers)",
+      self.total(),
+      self.groups,
+      self.out_of_depth,
+      self.crowded_out,
+      self.wanderers
+    )?;
+    for (kind, count) in self.placed.iter() {
+      write!(f, ", {} {}", count, kind.definition().name)?;
+    }
+    Ok(())
+  }
+}
+
+impl GameWorld {
+  /// Puts a new monster of the given kind at a spot, as tough as it would be
+  /// at `depth`. If someone's already there nothing happens and this gives
+  /// back `false`.
+  pub(crate) fn spawn_monster(&mut self, kind: MonsterKind, loc: Location, depth: i32) -> bool {
+    if self.creature_locations.contains_key(&loc) {
+      return false;
+    }
+    let monster = Creature::new_monster(self.next_creature_id, kind, depth);
+    self.next_creature_id += 1;
+    self.creature_locations.insert(loc, CreatureID(monster.id.0));
+    self.creature_list.push(monster);
+    true
+  }
+
+  /// Fills a freshly made level with monsters.
+  ///
+  /// Vault monsters go in their spots first. Then groups are rolled from the
+  /// level's band until the level's share of the monster density is used up.
+  /// Each group's leader has a chance to stand guard on one of the posts
+  /// given, and the rest of the pack starts out close by.
+  pub(crate) fn populate_level(&mut self, z: i32, hints: &PlacementHints, mut guard_posts: Vec<Location>) -> Result<SpawnStats, GenerationError> {
+    let mut stats = SpawnStats::default();
+    let chart = monster_chart_for_depth(z);
+    let deeper_chart = monster_chart_for_depth(z - DEPTH_BAND_SIZE);
+
+    for &(x, y) in hints.monster_spots.iter() {
+      let kind = chart.roll_with(&mut self.gen).kind;
+      if self.spawn_monster(kind, Location { x: x as i32, y: y as i32, z }, z) {
+        *stats.placed.entry(kind).or_insert(0) += 1;
+      } else {
+        stats.crowded_out += 1;
+      }
+    }
+
+    let area = self.levels[&z].width * self.levels[&z].height;
+    let wanted = area * self.monster_density / 10_000;
+    let placement = self.placement(z, &self.monster_placement_rules(z))?.requiring(1);
+    let mut rolled = 0;
+    // every group rolls at least one monster, so this always finishes.
+    while rolled < wanted {
+      let (group, depth) = if self.gen.next_u32().is_multiple_of(OUT_OF_DEPTH_ODDS) {
+        (deeper_chart.roll_with(&mut self.gen), z - DEPTH_BAND_SIZE)
+      } else {
+        (chart.roll_with(&mut self.gen), z)
+      };
+      let count = roll_between(&mut self.gen, group.min_count, group.max_count);
+      rolled += count;
+      stats.groups += 1;
+      let leader_spot = if !guard_posts.is_empty() && self.gen.next_u32() >> 30 == 0 {
+        let post_index = roll_between(&mut self.gen, 0, guard_posts.len() - 1);
+        let post = guard_posts.swap_remove(post_index);
+        if !placement.allows(self, post) {
+          stats.crowded_out += count;
+          continue;
+        }
+        post
+      } else {
+        self.pick_floor_for(&placement)?
+      };
+      self.spawn_group(group.kind, count, leader_spot, depth, &placement, &mut stats);
+    }
+    Ok(stats)
+  }
+
+  /// Puts a pack of `count` monsters on the level, the leader at the spot given
+  /// and the rest close by. Gives back the spots of every monster that fit.
+  fn spawn_group(&mut self, kind: MonsterKind, count: usize, leader_spot: Location, depth: i32, placement: &Placement, stats: &mut SpawnStats) -> Vec<Location> {
+    let mut spots = vec![leader_spot];
+    if count > 1 {
+      spots.extend(self.pack_spots_around(leader_spot, placement).into_iter().take(count - 1));
+    }
+    spots.retain(|&spot| self.spawn_monster(kind, spot, depth));
+    stats.crowded_out += count - spots.len();
+    *stats.placed.entry(kind).or_insert(0) += spots.len();
+    if depth != leader_spot.z {
+      stats.out_of_depth += spots.len();
+    }
+    spots
+  }
+
+  /// Might bring a wandering monster onto the player's level, somewhere out
+  /// of sight. It arrives knowing where the player was when it showed up.
+  pub(crate) fn spawn_wanderers(&mut self) {
+    let z = self.player_location.z;
+    let turns = match self.levels.get(&z) {
+      Some(level_ref) => level_ref.turns_spent,
+      None => return,
+    };
+    if !self.gen.next_u32().is_multiple_of(wandering_odds(z, turns)) {
+      return;
+    }
+    let player = self.player_location;
+    let limit = self.levels[&z].width * self.levels[&z].height * WANDERING_MONSTER_LIMIT / 10_000;
+    let monsters_here = self.creature_locations.keys().filter(|loc| loc.z == z && **loc != player).count();
+    if monsters_here >= limit {
+      return;
+    }
+    let rules = [
+      PlacementRule::NoCreatureWithin(0),
+      PlacementRule::OutOfSightOf(player),
+      PlacementRule::AwayFrom(player, WANDERING_DISTANCE),
+    ];
+    let placement = match self.placement(z, &rules) {
+      Ok(placement) => placement.requiring(2),
+      Err(_) => return,
+    };
+    let leader_spot = match self.pick_floor_for(&placement) {
+      Ok(spot) => spot,
+      // nowhere out of sight, so nothing can sneak up.
+      Err(_) => return,
+    };
+    let group = monster_chart_for_depth(z).roll_with(&mut self.gen);
+    let count = roll_between(&mut self.gen, group.min_count, group.max_count).min(limit - monsters_here);
+    let mut stats = self.spawn_stats.remove(&z).unwrap_or_default();
+    stats.groups += 1;
+    let spots = self.spawn_group(group.kind, count, leader_spot, z, &placement, &mut stats);
+    stats.wanderers += spots.len();
+    self.spawn_stats.insert(z, stats);
+    for spot in spots {
+      let cid = self.creature_locations[&spot].0;
+      if let Some(creature_mut) = self.creature_list.iter_mut().find(|creature_ref| creature_ref.id.0 == cid) {
+        creature_mut.alerted_to = Some(player);
+      }
+    }
+  }
+
+  /// The open floor close enough to a pack leader for the rest of the pack to
+  /// start out on, nearest first.
+  fn pack_spots_around(&self, leader_spot: Location, placement: &Placement) -> Vec<Location> {
+    let mut reached = HashSet::new();
+    reached.insert(leader_spot);
+    let mut frontier = vec![leader_spot];
+    let mut out = vec![];
+    for _ in 0..PACK_SPREAD {
+      let mut next_frontier = vec![];
+      for loc in frontier {
+        for neighbor in loc.neighbors() {
+          if self.terrain_at(neighbor) == Terrain::Floor && reached.insert(neighbor) {
+            next_frontier.push(neighbor);
+            if placement.allows(self, neighbor) {
+              out.push(neighbor);
+            }
+          }
+        }
+      }
+      frontier = next_frontier;
+    }
+    out
+  }
+}
+
+#[test]
+fn deeper_bands_have_tougher_monsters() {
+  let gen = &mut PCG32::new(8675309);
+  let kinds_in_band = |band: i32, gen: &mut PCG32| {
+    let chart = monster_chart_for_band(band);
+    let mut kinds = BTreeSet::new();
+    for _ in 0..2000 {
+      kinds.insert(chart.roll_with(gen).kind);
+    }
+    kinds
+  };
+  let top_kinds = kinds_in_band(0, gen);
+  assert!(!top_kinds.contains(&MonsterKind::AshShaman));
+  assert!(!top_kinds.contains(&MonsterKind::RockThrower));
+  let deep_kinds = kinds_in_band(3, gen);
+  for kind in [MonsterKind::Kestrel, MonsterKind::MagmaSpitter, MonsterKind::RockThrower, MonsterKind::AshShaman].iter() {
+    assert!(deep_kinds.contains(kind), "{:?} never shows up deep down", kind);
+  }
+}
+
+#[test]
+fn monster_density_scales_the_population() {
+  let mut sparse = GameWorld::new(1357);
+  let mut crowded = GameWorld::new(1357);
+  sparse.monster_density = DEFAULT_MONSTER_DENSITY / 2;
+  crowded.monster_density = DEFAULT_MONSTER_DENSITY * 2;
+  sparse.add_z_layer().unwrap();
+  crowded.add_z_layer().unwrap();
+  let z = sparse.deepest_depth;
+  let count_on = |game: &GameWorld| game.creature_locations.keys().filter(|loc| loc.z == z).count();
+  assert!(count_on(&sparse) < count_on(&crowded));
+  for game in [&sparse, &crowded].iter() {
+    let stats = &game.spawn_stats[&z];
+    assert_eq!(stats.total(), count_on(game), "{}", stats);
+  }
+}
+
+#[test]
+fn wanderers_come_sooner_the_longer_you_stay() {
+  assert!(wandering_odds(0, 0) > wandering_odds(-8, 0));
+  assert!(wandering_odds(-3, 0) > wandering_odds(-3, 1000));
+  assert_eq!(wandering_odds(-30, 1_000_000), WANDERING_MIN_ODDS);
+}
+
+#[test]
+fn wanderers_show_up_out_of_sight() {
+  let mut game = GameWorld::new(24680);
+  let z = game.player_location.z;
+  game.levels.get_mut(&z).unwrap().turns_spent = 1_000_000;
+  let player = game.player_location;
+  let seen = game.seen_from(player, 100);
+  let before: HashSet<Location> = game.creature_locations.keys().cloned().collect();
+  for _ in 0..(WANDERING_MIN_ODDS * 10) {
+    game.spawn_wanderers();
+  }
+  let wanderers: Vec<Location> = game.creature_locations.keys().filter(|loc| !before.contains(loc)).cloned().collect();
+  assert!(!wanderers.is_empty(), "nobody wandered in");
+  assert_eq!(game.spawn_stats[&z].wanderers, wanderers.len());
+  for loc in wanderers {
+    let cid = game.creature_locations[&loc].0;
+    let wanderer = game.creature_list.iter().find(|creature_ref| creature_ref.id.0 == cid).unwrap();
+    assert_eq!(wanderer.alerted_to, Some(player));
+    assert!(!seen.contains(&loc), "{:?} showed up in plain sight", loc);
+  }
+}
+
+#[test]
+fn wanderers_stop_once_the_level_is_full() {
+  let mut game = GameWorld::new(24680);
+  let z = game.player_location.z;
+  game.levels.get_mut(&z).unwrap().turns_spent = 1_000_000;
+  let limit = game.levels[&z].width * game.levels[&z].height * WANDERING_MONSTER_LIMIT / 10_000;
+  let monsters_here = |game: &GameWorld| game.creature_locations.keys().filter(|loc| loc.z == z && **loc != game.player_location).count();
+  for _ in 0..(WANDERING_MIN_ODDS * 200) {
+    game.spawn_wanderers();
+    assert!(monsters_here(&game) <= limit);
+  }
+  assert!(game.spawn_stats[&z].wanderers > 0, "nobody wandered in");
+  assert_eq!(monsters_here(&game), limit);
+}
@@ -0,0 +1,259 @@
+//! The staircases that link each level to the ones above and below it.
+//!
+//! Every staircase remembers exactly where it comes out, so the two ends of a
+//! flight of stairs don't have to line up. A staircase up is always linked
+//! when it's made, because the level above already exists. A staircase down
+//! stays unlinked until the level below it gets generated, and then every
+//! staircase down gets its own staircase up on the new level. The top level
+//! has one staircase up of its own that doesn't go anywhere: it's the way out
+//! of the dungeon.
+
+use super::*;
+
+/// The fewest staircases down that a level gets.
+pub const MIN_STAIRS_DOWN: usize = 2;
+/// The most staircases down that a level gets, not counting any from vaults.
+pub const MAX_STAIRS_DOWN: usize = 3;
+
+/// One end of a flight of stairs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Staircase {
+  pub x: i32,
+  pub y: i32,
+  /// Where taking these stairs puts you, `None` until that level exists.
+  pub destination: Option<Location>,
+}
+
+impl Level {
+  /// The staircase at the given position, if there is one.
+  pub fn staircase_at(&self, x: i32, y: i32) -> Option<Staircase> {
+    self.stairs.iter().find(|stairs_ref| stairs_ref.x == x && stairs_ref.y == y).cloned()
+  }
+
+  fn link_staircase(&mut self, x: i32, y: i32, destination: Location) {
+    if let Some(stairs_mut) = self.stairs.iter_mut().find(|stairs_ref| stairs_ref.x == x && stairs_ref.y == y) {
+      stairs_mut.destination = Some(destination);
+    }
+  }
+}
+
+impl GameWorld {
+  /// Puts the stairs onto a freshly made level.
+  ///
+  /// Every unlinked staircase down on the level above gets a staircase up here,
+  /// and then this level gets a few staircases down of its own. Vault stairs
+  /// come first, then the middles of rooms, then anywhere at all, but the
+  /// staircases down go in the far half of the level from the staircases up
+  /// whenever there's room for them there, and never right next to them.
+  /// Staircases up stay out of any locked room, so that nobody arrives
+  /// somewhere they can't get out of, and away from the vault's monsters.
+  pub(crate) fn place_stairs(&mut self, z: i32, hints: &PlacementHints) -> Result<(), GenerationError> {
+    let to_location = |&(x, y): &(usize, usize)| Location { x: x as i32, y: y as i32, z };
+    let above_z = z + 1;
+    let unlinked_down: Vec<(i32, i32)> = self
+      .levels
+      .get(&above_z)
+      .map(|level_ref| {
+        level_ref
+          .stairs
+          .iter()
+          .filter(|stairs_ref| stairs_ref.destination.is_none() && level_ref.terrain(stairs_ref.x, stairs_ref.y) == Terrain::StairsDown)
+          .map(|stairs_ref| (stairs_ref.x, stairs_ref.y))
+          .collect()
+      })
+      .unwrap_or(vec![]);
+    let mut up_rules = vec![];
+    if let Some(room) = hints.locked_room {
+      up_rules.push(PlacementRule::OutsideRect(room));
+    }
+    up_rules.extend(hints.stairs_down_spots.iter().map(|spot| PlacementRule::AwayFrom(to_location(spot), STAIRS_APART_DISTANCE)));
+    up_rules.extend(hints.monster_spots.iter().map(|spot| PlacementRule::AwayFrom(to_location(spot), ARRIVAL_SAFE_DISTANCE)));
+    let up_placement = self.placement(z, &up_rules)?;
+    for (x, y) in unlinked_down {
+      let spot = self.pick_floor_for(&up_placement)?;
+      self.set_terrain(spot, Terrain::StairsUp);
+      let level_mut = self.levels.get_mut(&z).unwrap();
+      level_mut.stairs.push(Staircase {
+        x: spot.x,
+        y: spot.y,
+        destination: Some(Location { x, y, z: above_z }),
+      });
+      self.levels.get_mut(&above_z).unwrap().link_staircase(x, y, spot);
+    }
+    if z == TOP_LEVEL_Z {
+      let spot = self.pick_floor_for(&up_placement)?;
+      self.set_terrain(spot, Terrain::StairsUp);
+      self.levels.get_mut(&z).unwrap().stairs.push(Staircase {
+        x: spot.x,
+        y: spot.y,
+        destination: None,
+      });
+    }
+
+    let analysis = self.analyze_level(z).ok_or(GenerationError::NoFloor { z })?;
+    let up_spots: Vec<Location> = self.levels[&z].stairs.iter().map(|stairs_ref| to_location(&(stairs_ref.x as usize, stairs_ref.y as usize))).collect();
+    let mut down_rules = vec![];
+    if let Some(region) = up_spots.first().and_then(|spot| analysis.region_at(spot.x, spot.y)) {
+      down_rules.push(PlacementRule::InRegion(region));
+    }
+    down_rules.extend(up_spots.iter().map(|&spot| PlacementRule::AwayFrom(spot, STAIRS_APART_DISTANCE)));
+    down_rules.push(PlacementRule::FarFromEntrances(analysis.max_distance().map(|max| max / 2).unwrap_or(0)));
+    let down_placement = self.placement(z, &down_rules)?;
+
+    let mut down_spots: Vec<Location> = hints.stairs_down_spots.iter().map(to_location).collect();
+    let wanted = roll_between(&mut self.gen, MIN_STAIRS_DOWN, MAX_STAIRS_DOWN);
+    let mut room_centers: Vec<(usize, usize)> =
+      hints.room_centers.iter().filter(|center| down_placement.allows(self, to_location(center))).cloned().collect();
+    while down_spots.len() < wanted {
+      let spot = if room_centers.is_empty() {
+        self.pick_floor_for(&down_placement)?
+      } else {
+        let center = room_centers.remove(roll_between(&mut self.gen, 0, room_centers.len() - 1));
+        let spot = to_location(&center);
+        if self.terrain_at(spot) != Terrain::Floor {
+          continue;
+        }
+        spot
+      };
+      if !down_spots.contains(&spot) {
+        down_spots.push(spot);
+      }
+    }
+    for spot in down_spots {
+      self.set_terrain(spot, Terrain::StairsDown);
+      self.levels.get_mut(&z).unwrap().stairs.push(Staircase {
+        x: spot.x,
+        y: spot.y,
+        destination: None,
+      });
+    }
+    Ok(())
+  }
+
+  /// Checks that every staircase on a level can be walked to from every other
+  /// one. Doors count as open, since any keys they need are on the level too.
+  pub fn stairs_connected(&self, z: i32) -> bool {
+    let analysis = match self.analyze_level(z) {
+      Some(analysis) => analysis,
+      None => return true,
+    };
+    let stairs = &self.levels[&z].stairs;
+    let first_region = stairs.first().and_then(|stairs_ref| analysis.region_at(stairs_ref.x, stairs_ref.y));
+    stairs
+      .iter()
+      .all(|stairs_ref| analysis.region_at(stairs_ref.x, stairs_ref.y).is_some() && analysis.region_at(stairs_ref.x, stairs_ref.y) == first_region)
+  }
+
+  /// Checks every staircase on every level in memory.
+  ///
+  /// All stairs terrain needs a staircase to go with it. Staircases up must
+  /// lead to a staircase down on the level above and the other way around,
+  /// with both ends pointing at each other. Only the deepest level can have
+  /// staircases down that don't go anywhere yet, and only the top level can
+  /// have a staircase up that doesn't go anywhere, the way out.
+  pub fn verify_stair_links(&self) -> Result<(), String> {
+    for (&z, level_ref) in self.levels.iter() {
+      for y in 0..level_ref.height as i32 {
+        for x in 0..level_ref.width as i32 {
+          let terrain = level_ref.terrain(x, y);
+          if (terrain == Terrain::StairsUp || terrain == Terrain::StairsDown) && level_ref.staircase_at(x, y).is_none() {
+            return Err(format!("{:?} at ({},{},{}) isn't a staircase", terrain, x, y, z));
+          }
+        }
+      }
+      for stairs_ref in level_ref.stairs.iter() {
+        let here = Location {
+          x: stairs_ref.x,
+          y: stairs_ref.y,
+          z,
+        };
+        let (expected_z, other_end_terrain) = match level_ref.terrain(here.x, here.y) {
+          Terrain::StairsUp => (z + 1, Terrain::StairsDown),
+          Terrain::StairsDown => (z - 1, Terrain::StairsUp),
+          other => return Err(format!("staircase at {:?} is on {:?}", here, other)),
+        };
+        let destination = match stairs_ref.destination {
+          Some(destination) => destination,
+          None if z == self.deepest_depth && expected_z < z => continue,
+          None if z == TOP_LEVEL_Z && expected_z > z => continue,
+          None => return Err(format!("staircase at {:?} doesn't lead anywhere", here)),
+        };
+        if destination.z != expected_z {
+          return Err(format!("staircase at {:?} leads to the wrong level: {:?}", here, destination));
+        }
+        if let Some(other_level_ref) = self.levels.get(&destination.z) {
+          if other_level_ref.terrain(destination.x, destination.y) != other_end_terrain {
+            return Err(format!("staircase at {:?} leads to {:?}, which isn't {:?}", here, destination, other_end_terrain));
+          }
+          match other_level_ref.staircase_at(destination.x, destination.y) {
+            Some(other_end) if other_end.destination == Some(here) => {}
+            _ => return Err(format!("staircase at {:?} leads to {:?}, which doesn't lead back", here, destination)),
+          }
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+#[test]
+fn stair_links_go_both_ways() {
+  for seed in 1..6 {
+    let mut game = GameWorld::new(seed);
+    for _ in 0..13 {
+      game.add_z_layer().unwrap();
+    }
+    assert_eq!(game.verify_stair_links(), Ok(()), "seed {}", seed);
+    for (&z, level_ref) in game.levels.iter() {
+      let down_count = level_ref.stairs.iter().filter(|stairs_ref| level_ref.terrain(stairs_ref.x, stairs_ref.y) == Terrain::StairsDown).count();
+      assert!(down_count >= MIN_STAIRS_DOWN, "seed {} z {}", seed, z);
+      assert!(game.stairs_connected(z), "seed {} z {}", seed, z);
+      let analysis = game.analyze_level(z).unwrap();
+      if let Some(max_distance) = analysis.max_distance() {
+        let farthest_down = level_ref
+          .stairs
+          .iter()
+          .filter(|stairs_ref| level_ref.terrain(stairs_ref.x, stairs_ref.y) == Terrain::StairsDown)
+          .filter_map(|stairs_ref| analysis.distance_at(stairs_ref.x, stairs_ref.y))
+          .max();
+        assert!(farthest_down >= Some(max_distance / 2), "seed {} z {}", seed, z);
+      }
+      if let Some(below_ref) = game.levels.get(&(z - 1)) {
+        let up_count = below_ref.stairs.iter().filter(|stairs_ref| below_ref.terrain(stairs_ref.x, stairs_ref.y) == Terrain::StairsUp).count();
+        assert_eq!(down_count, up_count, "seed {} z {}", seed, z);
+      }
+    }
+  }
+}
+
+#[test]
+fn taking_the_stairs_there_and_back() {
+  let mut game = GameWorld::new(777);
+  let top_z = game.player_location.z;
+  let player_cid = game.creature_locations[&game.player_location].0;
+  let stairs_ref = {
+    let top_ref = &game.levels[&top_z];
+    *top_ref.stairs.iter().find(|stairs_ref| top_ref.terrain(stairs_ref.x, stairs_ref.y) == Terrain::StairsDown).unwrap()
+  };
+  let start = Location {
+    x: stairs_ref.x,
+    y: stairs_ref.y,
+    z: top_z,
+  };
+  // clear out the monsters so that nothing gets in the way
+  game.creature_list.retain(|creature_ref| creature_ref.is_the_player);
+  game.creature_locations.clear();
+  game.creature_locations.insert(start, CreatureID(player_cid));
+  game.player_location = start;
+
+  game.change_floor(-1);
+  assert_eq!(game.player_location.z, top_z - 1);
+  assert_eq!(game.terrain_at(game.player_location), Terrain::StairsUp);
+  let arrival = game.player_location;
+
+  game.creature_list.retain(|creature_ref| creature_ref.is_the_player);
+  game.creature_locations.clear();
+  game.creature_locations.insert(arrival, CreatureID(player_cid));
+  game.change_floor(1);
+  assert_eq!(game.player_location, start);
+}
@@ -0,0 +1,206 @@
+//! The look and feel of each stretch of the dungeon.
+//!
+//! Every few levels the dungeon changes theme. A theme picks how the caves get
+//! grown, what extra terrain is scattered around, which monsters and items are
+//! more common, and what color the walls and floor are drawn in. The player
+//! gets a message whenever they cross into a different theme.
+
+use super::*;
+
+/// How many levels deep each theme goes before the next one starts.
+pub const THEME_BAND_SIZE: i32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Theme {
+  /// Plain old caves and tunnels.
+  UpperCaves,
+  /// Wide open caverns with frozen pillars of ice.
+  IceCaverns,
+  /// Cramped, twisting grottoes full of things to eat.
+  FungalGrottoes,
+  /// Hot and dangerous, and the magma spitters like it here.
+  MagmaDepths,
+}
+
+/// Everything that a theme changes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThemeDefinition {
+  pub name: &'static str,
+  /// Shown when the player comes into a level of this theme from another.
+  pub entry_message: &'static str,
+  /// If every level is a natural cave, instead of taking turns with the other
+  /// generators.
+  pub natural_caves: bool,
+  /// How likely each cell is to start out as a wall when growing caves.
+  pub cave_fill_percent: u32,
+  /// How many times the cave automaton runs over the map.
+  pub cave_iterations: usize,
+  /// How many patches of ice to try and freeze into each level.
+  pub ice_patches: usize,
+  /// Groups of monsters that are more common here, on top of the usual ones.
+  pub extra_monsters: &'static [(SpawnGroup, u32)],
+  /// Items that are more common here, on top of the usual ones.
+  pub extra_items: &'static [(Item, u32)],
+  /// What color the floor is drawn in, if it's not the usual.
+  pub floor_color: Option<u32>,
+  /// What color the walls are drawn in, if it's not the usual.
+  pub wall_color: Option<u32>,
+}
+
+impl Theme {
+  pub fn definition(self) -> ThemeDefinition {
+    match self {
+      Theme::UpperCaves => ThemeDefinition {
+        name: "the upper caves",
+        entry_message: "You climb back into the plain rock of the upper caves.",
+        natural_caves: false,
+        cave_fill_percent: CAVE_FILL_PERCENT,
+        cave_iterations: CAVE_ITERATIONS,
+        ice_patches: 0,
+        extra_monsters: &[],
+        extra_items: &[],
+        floor_color: None,
+        wall_color: None,
+      },
+      Theme::IceCaverns => ThemeDefinition {
+        name: "the ice caverns",
+        entry_message: "The air turns bitterly cold. You have entered the ice caverns.",
+        natural_caves: true,
+        cave_fill_percent: 43,
+        cave_iterations: 7,
+        ice_patches: 10,
+        extra_monsters: &[(SpawnGroup { kind: MonsterKind::RockThrower, min_count: 1, max_count: 2 }, 8)],
+        extra_items: &[(Item::BombBlast, 8), (Item::FoodSoup, 4)],
+        floor_color: Some(rgb32!(190, 205, 220)),
+        wall_color: Some(rgb32!(130, 150, 180)),
+      },
+      Theme::FungalGrottoes => ThemeDefinition {
+        name: "the fungal grottoes",
+        entry_message: "Soft mushrooms squish underfoot. You have entered the fungal grottoes.",
+        natural_caves: true,
+        cave_fill_percent: 46,
+        cave_iterations: 3,
+        ice_patches: 0,
+        extra_monsters: &[(SpawnGroup { kind: MonsterKind::Kestrel, min_count: 3, max_count: 5 }, 12)],
+        extra_items: &[(Item::FoodSoup, 10), (Item::FoodRock, 6), (Item::Fernweave(0), 6)],
+        floor_color: Some(rgb32!(120, 150, 95)),
+        wall_color: Some(rgb32!(85, 110, 60)),
+      },
+      Theme::MagmaDepths => ThemeDefinition {
+        name: "the magma depths",
+        entry_message: "Heat rolls up from below. You have entered the magma depths.",
+        natural_caves: false,
+        cave_fill_percent: CAVE_FILL_PERCENT,
+        cave_iterations: CAVE_ITERATIONS,
+        ice_patches: 0,
+        extra_monsters: &[
+          (SpawnGroup { kind: MonsterKind::MagmaSpitter, min_count: 2, max_count: 4 }, 12),
+          (SpawnGroup { kind: MonsterKind::AshShaman, min_count: 1, max_count: 1 }, 6),
+        ],
+        extra_items: &[(Item::BombIce, 8), (Item::PotionHealth, 6)],
+        floor_color: Some(rgb32!(150, 110, 90)),
+        wall_color: Some(rgb32!(135, 55, 35)),
+      },
+    }
+  }
+
+  /// The color that a piece of terrain is drawn in for this theme.
+  pub fn terrain_color(self, terrain: Terrain) -> u32 {
+    let definition = self.definition();
+    match terrain {
+      Terrain::Floor => definition.floor_color,
+      Terrain::Wall => definition.wall_color,
+      _ => None,
+    }
+    .unwrap_or(terrain.properties().color)
+  }
+}
+
+/// The theme of the level at the given depth.
+pub fn theme_for_depth(depth: i32) -> Theme {
+  match (depth.abs() / THEME_BAND_SIZE) % 4 {
+    0 => Theme::UpperCaves,
+    1 => Theme::IceCaverns,
+    2 => Theme::FungalGrottoes,
+    _ => Theme::MagmaDepths,
+  }
+}
+
+/// Freezes little patches of ice into a freshly generated map.
+///
+/// Ice only ever goes on plain floor that nothing is planned for, and any patch
+/// that would cut one part of the map off from the rest melts right away.
+pub fn add_ice_patches(map: &mut GeneratedMap, patch_count: usize, gen: &mut PCG32) {
+  let (width, height) = (map.terrain.width(), map.terrain.height());
+  if width < 3 || height < 3 {
+    return;
+  }
+  let hints = &map.hints;
+  let planned: HashSet<(usize, usize)> = hints
+    .room_centers
+    .iter()
+    .chain(hints.item_spots.iter())
+    .chain(hints.monster_spots.iter())
+    .chain(hints.stairs_down_spots.iter())
+    .chain(hints.key_spots.iter())
+    .cloned()
+    .collect();
+  for _ in 0..patch_count {
+    let mut spot = (roll_between(gen, 1, width - 2), roll_between(gen, 1, height - 2));
+    let mut frozen = vec![];
+    for _ in 0..roll_between(gen, 4, 10) {
+      if map.terrain[spot] == Terrain::Floor && !planned.contains(&spot) {
+        map.terrain[spot] = Terrain::Ice;
+        frozen.push(spot);
+      }
+      spot = match gen.next_u32() >> 30 {
+        0 => (spot.0, (spot.1 + 1).min(height - 2)),
+        1 => (spot.0, (spot.1 - 1).max(1)),
+        2 => ((spot.0 + 1).min(width - 2), spot.1),
+        _ => ((spot.0 - 1).max(1), spot.1),
+      };
+    }
+    if !frozen.is_empty() && LevelAnalysis::new(&Level::from_image(&map.terrain), &[]).region_count() > 1 {
+      for spot in frozen {
+        map.terrain[spot] = Terrain::Floor;
+      }
+    }
+  }
+}
+
+impl GameWorld {
+  /// Lets the player know when they've gone from one theme into another.
+  pub(crate) fn announce_theme_change(&mut self, old_z: i32, new_z: i32) {
+    let new_theme = theme_for_depth(new_z);
+    if theme_for_depth(old_z) != new_theme {
+      self.push_message(new_theme.definition().entry_message.to_string());
+    }
+  }
+}
+
+#[test]
+fn ice_patches_keep_the_map_in_one_piece() {
+  let gen = &mut PCG32::new(1122334455);
+  for _ in 0..10 {
+    let mut map = generate_level_map(&CellularCaves::default(), 50, 50, gen).unwrap();
+    add_ice_patches(&mut map, 30, gen);
+    assert!(map.terrain.iter().any(|(_, _, tile)| *tile == Terrain::Ice), "nothing froze");
+    assert_eq!(LevelAnalysis::new(&Level::from_image(&map.terrain), &[]).region_count(), 1);
+  }
+}
+
+#[test]
+fn going_down_into_a_new_theme() {
+  let mut game = GameWorld::new(31415);
+  while game.deepest_depth > -THEME_BAND_SIZE {
+    game.add_z_layer().unwrap();
+  }
+  let top_z = game.player_location.z;
+  let landing = game.pick_open_floor(top_z - 1).unwrap();
+  game.relocate_player(landing);
+  assert!(!game.messages.iter().any(|message| message.contains("ice caverns")));
+  let icy_landing = game.pick_open_floor(-THEME_BAND_SIZE).unwrap();
+  game.relocate_player(icy_landing);
+  assert_eq!(theme_for_depth(icy_landing.z), Theme::IceCaverns);
+  assert!(game.messages.iter().any(|message| message.contains("ice caverns")));
+}
@@ -0,0 +1,308 @@
+//! Traps hidden in the floor, searching around for them, and falling through
+//! the floor to the level below.
+//!
+//! Traps live in their own map, next to the items, so that they don't change
+//! what the terrain underneath them does. Every trap starts out hidden. The
+//! player finds them by searching or, the hard way, by stepping on them.
+//! Monsters live on their level, so they know where all of its traps are and
+//! never step on them. They also stay out of open pits, but a trapdoor looks
+//! like any other floor until something falls through it.
+
+use super::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum TrapKind {
+  /// Sends the player somewhere else on the level.
+  Teleport,
+  /// Drops the player down to the next level.
+  Pit,
+  /// Calls the nearby monsters over.
+  Alarm,
+  /// Shoots the player with a dart.
+  Dart,
+}
+
+impl ::std::fmt::Display for TrapKind {
+  fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+    match self {
+      TrapKind::Teleport => write!(f, "teleport trap"),
+      TrapKind::Pit => write!(f, "pit trap"),
+      TrapKind::Alarm => write!(f, "alarm trap"),
+      TrapKind::Dart => write!(f, "dart trap"),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Trap {
+  pub kind: TrapKind,
+  /// Hidden traps aren't drawn, and the player walks right into them.
+  pub hidden: bool,
+}
+
+/// How far an alarm trap can be heard from.
+pub const ALARM_RADIUS: i32 = 20;
+
+/// The damage step rolled against anything that falls down a level.
+pub const FALL_DAMAGE_STEP: i32 = 6;
+
+impl GameWorld {
+  /// Scatters hidden traps, pits, and trapdoors over the floor of a freshly
+  /// made level.
+  pub(crate) fn place_traps(&mut self, z: i32) -> Result<(), GenerationError> {
+    let depth_u32 = z.unsigned_abs();
+    let mut trap_frequencies = FrequencyChart::new(TrapKind::Dart, 10);
+    trap_frequencies.push_item(TrapKind::Alarm, 6);
+    trap_frequencies.push_item(TrapKind::Teleport, depth_u32 / 2 + 2);
+    trap_frequencies.push_item(TrapKind::Pit, depth_u32 / 2 + 1);
+    let area = self.levels[&z].width * self.levels[&z].height;
+    let trap_placement = self.placement(z, &[PlacementRule::Unclaimed])?;
+    for _ in 0..(area / 250) {
+      let spot = self.pick_floor_for(&trap_placement)?;
+      let kind = trap_frequencies.roll_with(&mut self.gen);
+      self.trap_locations.insert(spot, Trap { kind, hidden: true });
+    }
+    // Holes only go out in the open, so that there's usually a way around.
+    let mut hole_spots = vec![];
+    for hole_index in 0..(area / 400) {
+      let spot = self.pick_floor_for(&trap_placement)?;
+      let in_the_open = spot.neighbors().all(|neighbor| self.terrain_at(neighbor) == Terrain::Floor);
+      if in_the_open && trap_placement.allows(self, spot) {
+        self.set_terrain(spot, if hole_index % 2 == 0 { Terrain::Trapdoor } else { Terrain::Pit });
+        hole_spots.push(spot);
+      }
+    }
+    // but if they cut off any of the stairs they get filled back in.
+    if !self.stairs_connected(z) {
+      for spot in hole_spots {
+        self.set_terrain(spot, Terrain::Floor);
+      }
+    }
+    Ok(())
+  }
+
+  /// Looks all around the player for hidden traps, which takes a turn.
+  ///
+  /// Each hidden trap close by has an even chance of being found.
+  pub fn search(&mut self) {
+    let center = self.player_location;
+    let mut found = vec![];
+    for y in (center.y - 1)..=(center.y + 1) {
+      for x in (center.x - 1)..=(center.x + 1) {
+        let loc = Location { x, y, z: center.z };
+        let hidden = self.trap_locations.get(&loc).map(|trap_ref| trap_ref.hidden).unwrap_or(false);
+        if hidden && self.gen.next_u32() >> 31 == 0 {
+          let trap_mut = self.trap_locations.get_mut(&loc).unwrap();
+          trap_mut.hidden = false;
+          found.push(trap_mut.kind);
+        }
+      }
+    }
+    if found.is_empty() {
+      self.push_message("You search, but find nothing.".to_string());
+    } else {
+      for kind in found {
+        self.push_message(format!("You find a {}!", kind));
+      }
+    }
+    self.run_world_turn();
+  }
+
+  /// If there's a trap or an open pit here, monsters will go around it.
+  pub(crate) fn monster_avoids(&self, loc: Location) -> bool {
+    let properties = self.terrain_at(loc).properties();
+    self.trap_locations.contains_key(&loc) || (properties.falls_through && !properties.hidden)
+  }
+
+  /// Sets off whatever trap is under the player, if any.
+  pub(crate) fn spring_trap(&mut self) {
+    let here = self.player_location;
+    let kind = match self.trap_locations.get_mut(&here) {
+      Some(trap_mut) => {
+        trap_mut.hidden = false;
+        trap_mut.kind
+      }
+      None => return,
+    };
+    match kind {
+      TrapKind::Teleport => {
+        self.push_message("You step on a teleport trap! The world blurs around you.".to_string());
+        match self.pick_open_floor(here.z) {
+          Ok(destination) => self.relocate_player(destination),
+          Err(e) => self.push_message(format!("Couldn't teleport the player: {}", e)),
+        }
+      }
+      TrapKind::Pit => {
+        self.push_message("You step on a pit trap and the floor gives way!".to_string());
+        self.fall_through_floor(here);
+      }
+      TrapKind::Alarm => {
+        self.push_message("You step on an alarm trap! A loud bell rings out.".to_string());
+        for (loc, cid) in self.creature_locations.iter() {
+          if loc.z == here.z && loc.manhattan_distance(here) <= ALARM_RADIUS {
+            if let Some(creature_mut) = self.creature_list.iter_mut().find(|creature_ref| &creature_ref.id == cid) {
+              if !creature_mut.is_the_player {
+                creature_mut.alerted_to = Some(here);
+              }
+            }
+          }
+        }
+      }
+      TrapKind::Dart => {
+        let damage_roll = step(&mut self.gen, 4 + here.z.abs() / 3);
+        self.push_message(format!("A dart shoots out of the floor and hits you for {} damage!", damage_roll));
+        self.damage_player(damage_roll, DeathCause::Trap(TrapKind::Dart));
+      }
+    }
+  }
+
+  /// Drops whatever creature is at `from` onto a random floor of the level
+  /// below, making that level first if nobody has been down there yet. The
+  /// landing always hurts.
+  pub(crate) fn fall_through_floor(&mut self, from: Location) {
+    if self.terrain_at(from).properties().hidden {
+      // a trapdoor stays open once it's been found out.
+      self.set_terrain(from, Terrain::Pit);
+    }
+    if from.z == self.deepest_depth {
+      if let Err(e) = self.add_z_layer() {
+        self.push_message(format!("Couldn't generate level {}: {}", from.z - 1, e));
+        return;
+      }
+    }
+    if let Err(e) = self.touch_level(from.z - 1) {
+      self.push_message(format!("Couldn't load level {}: {}", from.z - 1, e));
+      return;
+    }
+    let destination = match self.pick_open_floor(from.z - 1) {
+      Ok(destination) => destination,
+      Err(e) => {
+        self.push_message(format!("Nowhere to land on level {}: {}", from.z - 1, e));
+        return;
+      }
+    };
+    let damage_roll = step(&mut self.gen, FALL_DAMAGE_STEP);
+    if from == self.player_location {
+      self.relocate_player(destination);
+      self.push_message(format!("You fall down to the next level, taking {} damage.", damage_roll));
+      self.damage_player(damage_roll, DeathCause::Fell);
+    } else if let Some(cid) = self.creature_locations.remove(&from) {
+      if let Some(creature_mut) = self.creature_list.iter_mut().find(|creature_ref| creature_ref.id == cid) {
+        creature_mut.hit_points -= damage_roll;
+        if creature_mut.hit_points < 1 {
+          // the player doesn't get credit for what gravity did.
+          creature_mut.experience_value = 0;
+        }
+      }
+      self.creature_locations.insert(destination, cid);
+      if from.z == self.player_location.z {
+        self.push_message("Something falls through the floor!".to_string());
+      }
+    }
+  }
+
+  /// Picks the player up and puts them down somewhere else, which could be on
+  /// another level. Any items at the destination get picked up, a level that
+  /// the player comes back to gets caught up, and crossing into a new theme
+  /// gets a message.
+  pub(crate) fn relocate_player(&mut self, destination: Location) {
+    let old_z = self.player_location.z;
+    let player_id = self
+      .creature_locations
+      .remove(&self.player_location)
+      .expect("The player wasn't where they should be!");
+    let old_creature = self.creature_locations.insert(destination, player_id);
+    debug_assert!(old_creature.is_none());
+    self.player_location = destination;
+    self.deepest_reached = self.deepest_reached.min(destination.z);
+    let player_mut = self.creature_list.iter_mut().find(|creature_ref| creature_ref.is_the_player).unwrap();
+    let floor_items = self.item_locations.entry(destination).or_default();
+    player_mut.inventory.append(floor_items);
+    if destination.z != old_z {
+      self.catch_up_level(destination.z);
+      self.announce_theme_change(old_z, destination.z);
+    }
+  }
+}
+
+/// Finds an open spot right next to the player that a trap can go on.
+#[cfg(test)]
+fn open_spot_by_player(game: &GameWorld) -> Option<Location> {
+  game
+    .player_location
+    .neighbors()
+    .find(|loc| game.terrain_at(*loc) == Terrain::Floor && !game.creature_locations.contains_key(loc))
+}
+
+#[test]
+fn stepping_on_a_trap_reveals_it() {
+  for seed in 1..20 {
+    let mut game = GameWorld::new(seed);
+    game.trap_locations.clear();
+    let spot = match open_spot_by_player(&game) {
+      Some(spot) => spot,
+      None => continue,
+    };
+    game.trap_locations.insert(
+      spot,
+      Trap {
+        kind: TrapKind::Dart,
+        hidden: true,
+      },
+    );
+    game.move_player(spot - game.player_location);
+    assert_eq!(game.player_location, spot);
+    assert!(!game.trap_locations[&spot].hidden);
+    assert!(game.messages.iter().any(|message| message.contains("dart")));
+    return;
+  }
+  panic!("no seed put the player next to some open floor");
+}
+
+#[test]
+fn searching_finds_nearby_traps() {
+  let mut game = GameWorld::new(54321);
+  game.trap_locations.clear();
+  // nothing gets to kill the player while they look around.
+  let player = game.player_location;
+  game.creature_locations.retain(|&loc, _| loc == player);
+  game.creature_list.retain(|creature_ref| creature_ref.is_the_player);
+  let spot = player + Location { x: 1, y: 1, z: 0 };
+  game.trap_locations.insert(
+    spot,
+    Trap {
+      kind: TrapKind::Alarm,
+      hidden: true,
+    },
+  );
+  for _ in 0..50 {
+    if !game.trap_locations[&spot].hidden {
+      break;
+    }
+    game.search();
+  }
+  assert_eq!(game.outcome, None);
+  assert!(!game.trap_locations[&spot].hidden);
+}
+
+#[test]
+fn falling_through_a_trapdoor() {
+  for seed in 1..20 {
+    let mut game = GameWorld::new(seed);
+    game.trap_locations.clear();
+    let spot = match open_spot_by_player(&game) {
+      Some(spot) => spot,
+      None => continue,
+    };
+    game.set_terrain(spot, Terrain::Trapdoor);
+    let top_z = game.player_location.z;
+    game.move_player(spot - game.player_location);
+    assert_eq!(game.player_location.z, top_z - 1);
+    assert!(game.terrain_at(game.player_location).is_walkable());
+    assert_eq!(game.terrain_at(spot), Terrain::Pit, "the trapdoor should stay open");
+    let player_ref = game.creature_list.iter().find(|creature_ref| creature_ref.is_the_player).unwrap();
+    assert!(player_ref.hit_points < player_ref.max_hit_points);
+    return;
+  }
+  panic!("no seed put the player next to some open floor");
+}
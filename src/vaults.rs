@@ -0,0 +1,309 @@
+//! Hand made vaults that get stamped into generated levels.
+
+use super::*;
+
+/// One cell of a vault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VaultTile {
+  /// Leaves whatever the generator made here alone.
+  Keep,
+  Wall,
+  Floor,
+  /// A floor with a guaranteed item.
+  Item,
+  /// A floor with a guaranteed monster.
+  Monster,
+  StairsDown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vault {
+  pub name: String,
+  pub width: usize,
+  pub height: usize,
+  /// Row by row, with `y = 0` being the bottom row just like the map.
+  tiles: Vec<VaultTile>,
+}
+
+impl Vault {
+  pub fn tile(&self, x: usize, y: usize) -> VaultTile {
+    self.tiles[y * self.width + x]
+  }
+}
+
+/// Parses vaults out of the prefab file format, see `data/vaults.txt`.
+pub fn parse_vaults(text: &str) -> Result<Vec<Vault>, String> {
+  let mut out = vec![];
+  let mut current: Option<(String, Vec<Vec<VaultTile>>)> = None;
+  let finish = |current: Option<(String, Vec<Vec<VaultTile>>)>, out: &mut Vec<Vault>| -> Result<(), String> {
+    if let Some((name, mut rows)) = current {
+      if rows.is_empty() {
+        return Err(format!("vault '{}' has no rows", name));
+      }
+      // text goes top to bottom, but the map's y goes bottom to top.
+      rows.reverse();
+      let width = rows.iter().map(|row| row.len()).max().unwrap();
+      let height = rows.len();
+      let mut tiles = vec![];
+      for row in rows {
+        let padding = width - row.len();
+        tiles.extend(row);
+        tiles.extend(::std::iter::repeat_n(VaultTile::Keep, padding));
+      }
+      out.push(Vault { name, width, height, tiles });
+    }
+    Ok(())
+  };
+  for (line_number, line) in text.lines().enumerate() {
+    let line = line.trim_end();
+    if line.starts_with(';') {
+      continue;
+    } else if let Some(name) = line.strip_prefix("vault:") {
+      finish(current.take(), &mut out)?;
+      current = Some((name.trim().to_string(), vec![]));
+    } else if line.is_empty() {
+      continue;
+    } else {
+      let mut row = vec![];
+      for ch in line.chars() {
+        row.push(match ch {
+          '?' => VaultTile::Keep,
+          '#' => VaultTile::Wall,
+          '.' => VaultTile::Floor,
+          '!' => VaultTile::Item,
+          'k' => VaultTile::Monster,
+          '>' => VaultTile::StairsDown,
+          other => return Err(format!("line {}: unknown vault tile '{}'", line_number + 1, other)),
+        });
+      }
+      match current.as_mut() {
+        Some((_, rows)) => rows.push(row),
+        None => return Err(format!("line {}: vault rows before any 'vault:' line", line_number + 1)),
+      }
+    }
+  }
+  finish(current.take(), &mut out)?;
+  Ok(out)
+}
+
+/// The vaults that ship with the game.
+pub fn builtin_vaults() -> Vec<Vault> {
+  parse_vaults(include_str!("../data/vaults.txt")).expect("the builtin vaults file is broken!")
+}
+
+fn is_open(terrain: Terrain) -> bool {
+  terrain.is_passable()
+}
+
+/// Flood fills the open cells from `start`, giving a grid of what was reached.
+fn flood_from(terrain: &VecImage<Terrain>, start: (usize, usize)) -> Vec<bool> {
+  let width = terrain.width();
+  let mut reached = vec![false; width * terrain.height()];
+  let mut open = vec![start];
+  while let Some((x, y)) = open.pop() {
+    if reached[y * width + x] || !is_open(terrain[(x, y)]) {
+      continue;
+    }
+    reached[y * width + x] = true;
+    if x > 0 {
+      open.push((x - 1, y));
+    }
+    if y > 0 {
+      open.push((x, y - 1));
+    }
+    if x + 1 < width {
+      open.push((x + 1, y));
+    }
+    if y + 1 < terrain.height() {
+      open.push((x, y + 1));
+    }
+  }
+  reached
+}
+
+fn all_open_connected(terrain: &VecImage<Terrain>) -> bool {
+  let start = match terrain.iter().find(|&(_, _, tile)| is_open(*tile)) {
+    Some((x, y, _)) => (x, y),
+    None => return false,
+  };
+  let reached = flood_from(terrain, start);
+  terrain.iter().all(|(x, y, tile)| !is_open(*tile) || reached[y * terrain.width() + x])
+}
+
+/// Digs the shortest tunnel from any open cell inside `area` to any open cell
+/// outside of it, without going anywhere near the cells in `avoid`. Returns if
+/// a tunnel was needed and dug.
+fn tunnel_out_of(terrain: &mut VecImage<Terrain>, area: Rect, avoid: &[(usize, usize)]) -> bool {
+  let (width, height) = (terrain.width(), terrain.height());
+  let mut came_from: Vec<Option<(usize, usize)>> = vec![None; width * height];
+  let mut visited = vec![false; width * height];
+  for &(x, y) in avoid.iter().filter(|&&(x, y)| x < width && y < height) {
+    visited[y * width + x] = true;
+  }
+  let mut queue = ::std::collections::VecDeque::new();
+  for (x, y, tile) in terrain.iter() {
+    if area.contains((x, y)) && is_open(*tile) {
+      visited[y * width + x] = true;
+      queue.push_back((x, y));
+    }
+  }
+  while let Some((x, y)) = queue.pop_front() {
+    if !area.contains((x, y)) && is_open(terrain[(x, y)]) {
+      // walk back, opening things up
+      let mut here = came_from[y * width + x];
+      while let Some((px, py)) = here {
+        if area.contains((px, py)) {
+          break;
+        }
+        terrain[(px, py)] = Terrain::Floor;
+        here = came_from[py * width + px];
+      }
+      return true;
+    }
+    // don't dig through the vault's own walls or the edge of the map
+    let neighbors = [(x + 1, y), (x.wrapping_sub(1), y), (x, y + 1), (x, y.wrapping_sub(1))];
+    for &(nx, ny) in neighbors.iter() {
+      if nx < 1 || ny < 1 || nx >= width - 1 || ny >= height - 1 || visited[ny * width + nx] {
+        continue;
+      }
+      if area.contains((nx, ny)) && !is_open(terrain[(nx, ny)]) {
+        continue;
+      }
+      visited[ny * width + nx] = true;
+      came_from[ny * width + nx] = Some((x, y));
+      queue.push_back((nx, ny));
+    }
+  }
+  false
+}
+
+/// Tries to stamp the vault somewhere on the map.
+///
+/// Spots are only used if the vault stays inside the outer wall, doesn't cover
+/// any cell in `avoid`, and if all of the open space on the map is still in
+/// one connected piece afterward (digging a tunnel out of the vault if that's
+/// what it takes, but never through the cells in `avoid`). When a spot works,
+/// the vault's items, monsters and stairs are added to the hints.
+pub fn stamp_vault(map: &mut GeneratedMap, vault: &Vault, avoid: &[(usize, usize)], gen: &mut PCG32) -> bool {
+  let (width, height) = (map.terrain.width(), map.terrain.height());
+  if vault.width + 2 > width || vault.height + 2 > height {
+    return false;
+  }
+  for _ in 0..20 {
+    let area = Rect {
+      x: roll_between(gen, 1, width - 1 - vault.width),
+      y: roll_between(gen, 1, height - 1 - vault.height),
+      width: vault.width,
+      height: vault.height,
+    };
+    if avoid.iter().any(|&spot| area.contains(spot)) {
+      continue;
+    }
+    let mut stamped = map.terrain.clone();
+    let mut hints = PlacementHints::default();
+    for y in 0..vault.height {
+      for x in 0..vault.width {
+        let spot = (area.x + x, area.y + y);
+        stamped[spot] = match vault.tile(x, y) {
+          VaultTile::Keep => continue,
+          VaultTile::Wall => Terrain::Wall,
+          VaultTile::Floor => Terrain::Floor,
+          VaultTile::Item => {
+            hints.item_spots.push(spot);
+            Terrain::Floor
+          }
+          VaultTile::Monster => {
+            hints.monster_spots.push(spot);
+            Terrain::Floor
+          }
+          VaultTile::StairsDown => {
+            hints.stairs_down_spots.push(spot);
+            Terrain::StairsDown
+          }
+        };
+      }
+    }
+    let connected = all_open_connected(&stamped) || (tunnel_out_of(&mut stamped, area, avoid) && all_open_connected(&stamped));
+    if !connected {
+      continue;
+    }
+    map.terrain = stamped;
+    map.hints.item_spots.extend(hints.item_spots);
+    map.hints.monster_spots.extend(hints.monster_spots);
+    map.hints.stairs_down_spots.extend(hints.stairs_down_spots);
+    return true;
+  }
+  false
+}
+
+#[test]
+fn builtin_vaults_parse() {
+  let vaults = builtin_vaults();
+  assert!(vaults.len() >= 4);
+  let nook = vaults.iter().find(|vault| vault.name == "Treasure Nook").unwrap();
+  assert_eq!((nook.width, nook.height), (7, 5));
+  // the top row of the text is the highest y
+  assert_eq!(nook.tile(0, 4), VaultTile::Keep);
+  assert_eq!(nook.tile(1, 4), VaultTile::Wall);
+  assert_eq!(nook.tile(3, 2), VaultTile::Monster);
+  assert_eq!(nook.tile(3, 0), VaultTile::Floor);
+}
+
+#[test]
+fn parse_vaults_rejects_junk() {
+  assert!(parse_vaults("vault: Bad\n#@#\n").is_err());
+  assert!(parse_vaults("###\n").is_err());
+  assert!(parse_vaults("vault: Empty\n").is_err());
+}
+
+#[test]
+fn stamped_vaults_stay_connected() {
+  let gen = &mut PCG32::new(2018);
+  let vaults = builtin_vaults();
+  let mut stamp_count = 0;
+  for i in 0..20 {
+    let mut map = CellularCaves::default().generate(50, 50, gen).unwrap();
+    let vault = &vaults[i % vaults.len()];
+    if stamp_vault(&mut map, vault, &[], gen) {
+      stamp_count += 1;
+      assert!(all_open_connected(&map.terrain), "{} cut off part of the map", vault.name);
+    }
+  }
+  assert!(stamp_count > 10);
+}
+
+#[test]
+fn vaults_leave_the_locked_room_alone() {
+  let gen = &mut PCG32::new(1034);
+  let vaults = builtin_vaults();
+  let rooms = RoomsAndCorridors {
+    max_rooms: 14,
+    min_size: 4,
+    max_size: 9,
+  };
+  let mut checked = 0;
+  for i in 0..200 {
+    let mut map = rooms.generate(50, 50, gen).unwrap();
+    let room = match map.hints.locked_room {
+      Some(room) => room,
+      None => continue,
+    };
+    let door = map.terrain.iter().find(|&(_, _, tile)| *tile == Terrain::DoorLocked).map(|(x, y, _)| (x, y)).unwrap();
+    let avoid = protected_cells(&map.hints);
+    if !stamp_vault(&mut map, &vaults[i % vaults.len()], &avoid, gen) {
+      continue;
+    }
+    checked += 1;
+    for &key_spot in map.hints.key_spots.iter() {
+      assert_eq!(map.terrain[key_spot], Terrain::Floor);
+    }
+    assert_eq!(map.terrain[door], Terrain::DoorLocked);
+    // with the door shut for good, nothing outside can get into the room.
+    let mut sealed = map.terrain.clone();
+    sealed[door] = Terrain::Wall;
+    let reached = flood_from(&sealed, map.hints.key_spots[0]);
+    let (cx, cy) = room.center();
+    assert!(!reached[cy * sealed.width() + cx], "a second way into the locked room");
+  }
+  assert!(checked > 5);
+}